@@ -0,0 +1,53 @@
+//! Exports a book to a single self-contained HTML file, written to standard output.
+//!
+//! Usage: `leedor-export <book> [<chapters>]`, where `<chapters>` is a chapter like `3` or a
+//! range of chapters like `3-5`. Chapters are the documents in the book's spine, counting from 1
+//! and including any that aren't in the linear reading order, such as pop-up notes. Without it,
+//! the whole book is exported.
+
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::{env, process};
+
+const USAGE: &str = "usage: leedor-export <book> [<chapters>]";
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("leedor-export: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (path, chapters) = match args.as_slice() {
+        [path] => (path, None),
+        [path, chapters] => (path, Some(parse_chapters(chapters)?)),
+        _ => return Err(USAGE.into()),
+    };
+    let html = leedor::export_html(fs::read(path)?, chapters)?;
+    io::stdout().write_all(html.as_bytes())?;
+    Ok(())
+}
+
+/// Parses a chapter or range of chapters, which are spine positions counting from 1, into the
+/// range of spine indices it covers.
+fn parse_chapters(chapters: &str) -> Result<Range<usize>, Box<dyn Error>> {
+    let invalid = || format!("invalid chapters {}", chapters);
+    let mut bounds = chapters.splitn(2, '-');
+    let first: usize = bounds
+        .next()
+        .unwrap_or_default()
+        .parse()
+        .map_err(|_| invalid())?;
+    let last: usize = match bounds.next() {
+        Some(last) => last.parse().map_err(|_| invalid())?,
+        None => first,
+    };
+    if first == 0 || last < first {
+        return Err(invalid().into());
+    }
+    Ok(first - 1..last)
+}
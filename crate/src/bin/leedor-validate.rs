@@ -0,0 +1,36 @@
+//! Checks a book for problems, printing one per line to standard output.
+//!
+//! Usage: `leedor-validate <book>`. The exit status is 1 when the book has errors and 2 when it
+//! can't be read at all, while books with only warnings pass.
+
+use leedor::Severity;
+use std::error::Error;
+use std::fs;
+use std::{env, process};
+
+const USAGE: &str = "usage: leedor-validate <book>";
+
+fn main() {
+    match run() {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("leedor-validate: {}", err);
+            process::exit(2);
+        }
+    }
+}
+
+/// Validates the book named in the arguments, returning whether it's free of errors.
+fn run() -> Result<bool, Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let path = match args.as_slice() {
+        [path] => path,
+        _ => return Err(USAGE.into()),
+    };
+    let diagnostics = leedor::validate(&fs::read(path)?);
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+    Ok(diagnostics.iter().all(|d| d.severity != Severity::Error))
+}
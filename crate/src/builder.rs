@@ -0,0 +1,224 @@
+//! Writes minimal EPUB 2 books in memory, so that formats converted to EPUB can be read with
+//! everything `Epub` already does.
+
+use crate::error::Result;
+use minidom::Element;
+use std::io::{Cursor, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const XHTML_NS: &str = "http://www.w3.org/1999/xhtml";
+const OPS_NS: &str = "http://www.idpf.org/2007/ops";
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#;
+
+struct Chapter {
+    title: String,
+    body: Element,
+}
+
+struct Resource {
+    href: String,
+    media_type: String,
+    bytes: Vec<u8>,
+}
+
+pub struct EpubBuilder {
+    authors: Vec<String>,
+    chapters: Vec<Chapter>,
+    identifier: String,
+    language: String,
+    resources: Vec<Resource>,
+    title: String,
+}
+
+impl EpubBuilder {
+    pub fn new(identifier: &str, title: &str, language: &str) -> EpubBuilder {
+        EpubBuilder {
+            authors: vec![],
+            chapters: vec![],
+            identifier: identifier.to_string(),
+            language: language.to_string(),
+            resources: vec![],
+            title: title.to_string(),
+        }
+    }
+
+    pub fn author(&mut self, name: &str) -> &mut EpubBuilder {
+        self.authors.push(name.to_string());
+        self
+    }
+
+    /// Appends a chapter to the spine and the table of contents. `body` is the XHTML `body`
+    /// element, which may link to other chapters by their `chapter_href` and use `epub:type`.
+    pub fn chapter(&mut self, title: &str, body: Element) -> &mut EpubBuilder {
+        self.chapters.push(Chapter {
+            title: title.to_string(),
+            body,
+        });
+        self
+    }
+
+    /// Adds a file that chapters refer to by `href`, relative to them.
+    pub fn resource(&mut self, href: &str, media_type: &str, bytes: Vec<u8>) -> &mut EpubBuilder {
+        self.resources.push(Resource {
+            href: href.to_string(),
+            media_type: media_type.to_string(),
+            bytes,
+        });
+        self
+    }
+
+    pub fn build(&self) -> Result<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file("mimetype", stored)?;
+        zip.write_all(b"application/epub+zip")?;
+        zip.start_file("META-INF/container.xml", FileOptions::default())?;
+        zip.write_all(CONTAINER_XML.as_bytes())?;
+        zip.start_file("OEBPS/content.opf", FileOptions::default())?;
+        zip.write_all(self.opf().as_bytes())?;
+        zip.start_file("OEBPS/toc.ncx", FileOptions::default())?;
+        zip.write_all(self.ncx().as_bytes())?;
+        for (idx, chapter) in self.chapters.iter().enumerate() {
+            zip.start_file(
+                format!("OEBPS/{}", chapter_href(idx)),
+                FileOptions::default(),
+            )?;
+            chapter_doc(chapter).write_to(&mut zip)?;
+        }
+        for resource in &self.resources {
+            zip.start_file(format!("OEBPS/{}", resource.href), FileOptions::default())?;
+            zip.write_all(&resource.bytes)?;
+        }
+        Ok(zip.finish()?.into_inner())
+    }
+
+    fn opf(&self) -> String {
+        let mut metadata = format!(
+            "<dc:identifier id=\"uid\">{}</dc:identifier>\
+             <dc:title>{}</dc:title><dc:language>{}</dc:language>",
+            escape(&self.identifier),
+            escape(&self.title),
+            escape(&self.language)
+        );
+        for author in &self.authors {
+            metadata.push_str(&format!("<dc:creator>{}</dc:creator>", escape(author)));
+        }
+        let mut manifest = String::from(
+            "<item id=\"ncx\" href=\"toc.ncx\" media-type=\"application/x-dtbncx+xml\"/>",
+        );
+        let mut spine = String::new();
+        for idx in 0..self.chapters.len() {
+            manifest.push_str(&format!(
+                "<item id=\"chapter{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>",
+                idx + 1,
+                chapter_href(idx)
+            ));
+            spine.push_str(&format!("<itemref idref=\"chapter{}\"/>", idx + 1));
+        }
+        for (idx, resource) in self.resources.iter().enumerate() {
+            manifest.push_str(&format!(
+                "<item id=\"resource{}\" href=\"{}\" media-type=\"{}\"/>",
+                idx + 1,
+                escape(&resource.href),
+                escape(&resource.media_type)
+            ));
+        }
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"2.0\" \
+             unique-identifier=\"uid\">\
+             <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">{}</metadata>\
+             <manifest>{}</manifest><spine toc=\"ncx\">{}</spine></package>",
+            metadata, manifest, spine
+        )
+    }
+
+    fn ncx(&self) -> String {
+        let mut nav_points = String::new();
+        for (idx, chapter) in self.chapters.iter().enumerate() {
+            nav_points.push_str(&format!(
+                "<navPoint id=\"np-{0}\" playOrder=\"{0}\"><navLabel><text>{1}</text></navLabel>\
+                 <content src=\"{2}\"/></navPoint>",
+                idx + 1,
+                escape(&chapter.title),
+                chapter_href(idx)
+            ));
+        }
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <ncx xmlns=\"http://www.daisy.org/z3986/2005/ncx/\" version=\"2005-1\">\
+             <head><meta name=\"dtb:uid\" content=\"{}\"/></head>\
+             <docTitle><text>{}</text></docTitle><navMap>{}</navMap></ncx>",
+            escape(&self.identifier),
+            escape(&self.title),
+            nav_points
+        )
+    }
+}
+
+/// The href of the chapter at `idx`, relative to the other chapters.
+pub fn chapter_href(idx: usize) -> String {
+    format!("chapter{}.xhtml", idx + 1)
+}
+
+fn chapter_doc(chapter: &Chapter) -> Element {
+    let head = Element::builder("head")
+        .append(
+            Element::builder("title")
+                .append(chapter.title.clone())
+                .build(),
+        )
+        .build();
+    Element::builder("html")
+        .ns(XHTML_NS)
+        .attr("xmlns:epub", OPS_NS)
+        .append(head)
+        .append(chapter.body.clone())
+        .build()
+}
+
+/// Escapes text for use in markup, both as content and in attribute values.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::Epub;
+
+    #[test]
+    fn built_epub_opens() -> Result<()> {
+        let mut builder = EpubBuilder::new("urn:test:1", "Tom & Jerry", "en");
+        builder.author("Hanna Barbera");
+        let one = Element::builder("body")
+            .append(Element::builder("p").append("One".to_string()).build())
+            .append(Element::builder("img").attr("src", "cover.png").build())
+            .build();
+        let two = Element::builder("body")
+            .append(Element::builder("p").append("Two".to_string()).build())
+            .build();
+        builder
+            .chapter("First", one)
+            .chapter("Second", two)
+            .resource("cover.png", "image/png", b"png".to_vec());
+        let mut epub = Epub::new(builder.build()?)?;
+        let toc = epub.toc()?;
+        let titles: Vec<_> = toc.iter().map(|i| i.text.as_str()).collect();
+        assert_eq!(vec!["First", "Second"], titles);
+        let chapter = epub.chapter(0)?;
+        assert!(chapter.contains("One"));
+        assert!(chapter.contains("data:image/png;base64,cG5n"));
+        assert!(epub.next_chapter()?.contains("Two"));
+        Ok(())
+    }
+}
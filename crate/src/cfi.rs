@@ -0,0 +1,177 @@
+//! A subset of EPUB Canonical Fragment Identifiers: paths of element steps such as
+//! `epubcfi(/6/4[chap01ref]!/4[body01]/10[para05])`. Character offsets, ranges and the like are
+//! accepted but ignored, so a CFI resolves to an element rather than a point in its text.
+
+use crate::error::Result;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Characters that stand for themselves in an assertion only when escaped with a `^`.
+const ESCAPED_CHARS: [char; 7] = ['^', '[', ']', '(', ')', ',', ';'];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub index: usize,
+    pub id: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cfi {
+    /// Steps through the package document, down to a spine `itemref`.
+    pub package_path: Vec<Step>,
+    /// Steps through the content document the `itemref` points at.
+    pub doc_path: Vec<Step>,
+}
+
+impl Cfi {
+    pub fn parse(cfi: &str) -> Result<Cfi> {
+        let cfi = cfi.trim();
+        if !cfi.starts_with("epubcfi(") || !cfi.ends_with(')') {
+            return Err("invalid CFI".into());
+        }
+        let mut chars = cfi["epubcfi(".len()..cfi.len() - 1].chars().peekable();
+        let mut package_path = vec![];
+        let mut doc_path = vec![];
+        let mut in_doc = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '/' if in_doc => doc_path.push(parse_step(&mut chars)?),
+                '/' => package_path.push(parse_step(&mut chars)?),
+                '!' => in_doc = true,
+                // only the common parent of a range is used
+                ',' => break,
+                // the assertions of offsets, which are skipped like the offsets themselves
+                '[' => {
+                    parse_assertion(&mut chars)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(Cfi {
+            package_path,
+            doc_path,
+        })
+    }
+}
+
+impl fmt::Display for Cfi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "epubcfi(")?;
+        write_steps(f, &self.package_path)?;
+        write!(f, "!")?;
+        write_steps(f, &self.doc_path)?;
+        write!(f, ")")
+    }
+}
+
+/// Parses a step right after its `/`: an index and an optional id assertion.
+fn parse_step(chars: &mut Peekable<Chars>) -> Result<Step> {
+    let mut digits = String::new();
+    while let Some(c) = chars.peek().cloned().filter(char::is_ascii_digit) {
+        digits.push(c);
+        chars.next();
+    }
+    let index = digits.parse().map_err(|_| "invalid CFI step")?;
+    let id = match chars.peek() {
+        Some('[') => {
+            chars.next();
+            Some(parse_assertion(chars)?)
+        }
+        _ => None,
+    };
+    Ok(Step { index, id })
+}
+
+/// Parses an assertion right after its `[`, up to the first `]` that isn't escaped. A `^` makes
+/// the next character a literal one, and parameters, which start at an unescaped `;`, are
+/// dropped.
+fn parse_assertion(chars: &mut Peekable<Chars>) -> Result<String> {
+    let mut value = String::new();
+    let mut in_params = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '^' => {
+                let escaped = chars.next().ok_or("unterminated CFI assertion")?;
+                if !in_params {
+                    value.push(escaped);
+                }
+            }
+            ']' => return Ok(value),
+            ';' => in_params = true,
+            _ if !in_params => value.push(c),
+            _ => {}
+        }
+    }
+    Err("unterminated CFI assertion".into())
+}
+
+fn write_steps(f: &mut fmt::Formatter, steps: &[Step]) -> fmt::Result {
+    for step in steps {
+        write!(f, "/{}", step.index)?;
+        if let Some(id) = &step.id {
+            write!(f, "[")?;
+            for c in id.chars() {
+                if ESCAPED_CHARS.contains(&c) {
+                    write!(f, "^")?;
+                }
+                write!(f, "{}", c)?;
+            }
+            write!(f, "]")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() -> Result<()> {
+        let cfi = Cfi::parse("epubcfi(/6/4[chap01ref]!/4[body01]/10[para05]/3:10)")?;
+        assert_eq!(2, cfi.package_path.len());
+        assert_eq!(Some("chap01ref".to_string()), cfi.package_path[1].id);
+        assert_eq!(
+            vec![4, 10, 3],
+            cfi.doc_path.iter().map(|s| s.index).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            "epubcfi(/6/4[chap01ref]!/4[body01]/10[para05]/3)",
+            cfi.to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn escaped_assertions() -> Result<()> {
+        let cfi = Cfi::parse("epubcfi(/6/4[a^]b^^c]!/4[x^,y^;z;s=b]/2[^[^(^)^]])")?;
+        assert_eq!(Some("a]b^c".to_string()), cfi.package_path[1].id);
+        assert_eq!(Some("x,y;z".to_string()), cfi.doc_path[0].id);
+        assert_eq!(Some("[()]".to_string()), cfi.doc_path[1].id);
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        for id in &["a^b", "a]b", "a[b", "a,b", "a;b", "^[],;"] {
+            let step = Step {
+                index: 4,
+                id: Some(id.to_string()),
+            };
+            let cfi = Cfi {
+                package_path: vec![step.clone()],
+                doc_path: vec![step],
+            };
+            assert_eq!(cfi, Cfi::parse(&cfi.to_string())?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Cfi::parse("/6/4!/4").is_err());
+        assert!(Cfi::parse("epubcfi(/6/x)").is_err());
+        assert!(Cfi::parse("epubcfi(/6/4[chap01ref)").is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! Comic book archives (CBZ): zips of page images, read in the natural order of their names,
+//! with optional metadata in a `ComicInfo.xml`.
+
+use crate::document::{Document, Metadata};
+use crate::epub::TocItem;
+use crate::error::Result;
+use crate::layout::Direction;
+use crate::xml::parse_xml;
+use minidom::Element;
+use std::cmp::Ordering;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+type Zip = ZipArchive<Cursor<Vec<u8>>>;
+
+const PAGE_TYPES: &[(&str, &str)] = &[
+    ("gif", "image/gif"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("png", "image/png"),
+    ("webp", "image/webp"),
+];
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ComicInfo {
+    pub title: Option<String>,
+    pub series: Option<String>,
+    pub number: Option<String>,
+    pub writer: Option<String>,
+    /// Whether pages are read from right to left, like manga.
+    pub right_to_left: bool,
+}
+
+impl ComicInfo {
+    fn from_element(elem: &Element) -> ComicInfo {
+        let field = |name: &str| {
+            elem.children()
+                .find(|c| c.name() == name)
+                .map(|c| c.text().trim().to_string())
+                .filter(|t| !t.is_empty())
+        };
+        ComicInfo {
+            title: field("Title"),
+            series: field("Series"),
+            number: field("Number"),
+            writer: field("Writer"),
+            right_to_left: field("Manga").unwrap_or_default() == "YesAndRightToLeft",
+        }
+    }
+
+    /// The title, or for issues without one the series and issue number, e.g. `Series #3`.
+    fn full_title(&self) -> Option<String> {
+        match (&self.title, &self.series, &self.number) {
+            (Some(title), _, _) => Some(title.clone()),
+            (None, Some(series), Some(number)) => Some(format!("{} #{}", series, number)),
+            (None, series, _) => series.clone(),
+        }
+    }
+}
+
+pub struct Comic {
+    current_idx: usize,
+    info: ComicInfo,
+    /// Names of the page images in the archive, in reading order.
+    pages: Vec<String>,
+    zip: Zip,
+}
+
+impl Comic {
+    pub fn new(bytes: Vec<u8>) -> Result<Comic> {
+        let mut zip = ZipArchive::new(Cursor::new(bytes))?;
+        let mut pages = vec![];
+        let mut info_name = None;
+        for i in 0..zip.len() {
+            let name = zip.by_index(i)?.name().to_string();
+            // resource forks macOS leaves behind look like images too
+            if name.starts_with("__MACOSX/") {
+                continue;
+            }
+            if page_media_type(&name).is_some() {
+                pages.push(name);
+            } else if name.to_lowercase() == "comicinfo.xml" {
+                info_name = Some(name);
+            }
+        }
+        if pages.is_empty() {
+            return Err("no pages in comic book archive".into());
+        }
+        pages.sort_by(|a, b| natural_cmp(a, b));
+        let info = match info_name {
+            Some(name) => ComicInfo::from_element(&parse_xml(zip.by_name(&name)?)?),
+            None => ComicInfo::default(),
+        };
+        Ok(Comic {
+            current_idx: 0,
+            info,
+            pages,
+            zip,
+        })
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn current_idx(&self) -> usize {
+        self.current_idx
+    }
+
+    pub fn direction(&self) -> Direction {
+        if self.info.right_to_left {
+            Direction::Rtl
+        } else {
+            Direction::Ltr
+        }
+    }
+
+    /// Moves to page `idx`, returning an `img` with the page embedded in it.
+    pub fn page(&mut self, idx: usize) -> Result<String> {
+        let name = self.pages.get(idx).ok_or("page_idx out of range")?;
+        let media_type = page_media_type(name).unwrap_or_default();
+        let mut bytes = vec![];
+        self.zip.by_name(name)?.read_to_end(&mut bytes)?;
+        let mut src = format!("data:{};base64,", media_type);
+        base64::encode_config_buf(&bytes, base64::STANDARD, &mut src);
+        self.current_idx = idx;
+        Ok(format!("<img src=\"{}\" alt=\"Page {}\"/>", src, idx + 1))
+    }
+}
+
+/// A comic's pages are its chapters.
+impl Document for Comic {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            title: self.info.full_title(),
+            authors: self.info.writer.iter().cloned().collect(),
+            language: None,
+        }
+    }
+
+    fn toc(&mut self) -> Result<Vec<TocItem>> {
+        Ok(vec![])
+    }
+
+    fn chapter_count(&self) -> usize {
+        self.page_count()
+    }
+
+    fn current_idx(&self) -> Result<usize> {
+        Ok(Comic::current_idx(self))
+    }
+
+    fn chapter(&mut self, idx: usize) -> Result<String> {
+        self.page(idx)
+    }
+
+    fn chapter_by_link(&mut self, _link: &str) -> Result<String> {
+        Err("comic pages have no links".into())
+    }
+
+    fn resource(&mut self, path: &Path) -> Result<Vec<u8>> {
+        let name = path.to_str().ok_or("invalid path")?;
+        let mut bytes = vec![];
+        self.zip.by_name(name)?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn direction(&self) -> Direction {
+        Comic::direction(self)
+    }
+
+    fn is_paged_images(&self) -> bool {
+        true
+    }
+}
+
+/// The media type of a page image, going by its file extension.
+pub fn page_media_type(name: &str) -> Option<&'static str> {
+    let extension = name.rsplit('.').next()?.to_lowercase();
+    PAGE_TYPES
+        .iter()
+        .find(|(e, _)| *e == extension)
+        .map(|(_, media_type)| *media_type)
+}
+
+/// Compares names so that numbers in them sort by value, e.g. `page2.jpg` before `page10.jpg`.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chunks = chunks(a);
+    let mut b_chunks = chunks(b);
+    loop {
+        let ordering = match (a_chunks.next(), b_chunks.next()) {
+            (Some(a_chunk), Some(b_chunk)) => {
+                let a_number = a_chunk.parse::<u64>();
+                let b_number = b_chunk.parse::<u64>();
+                match (a_number, b_number) {
+                    (Ok(a_number), Ok(b_number)) => a_number.cmp(&b_number),
+                    _ => a_chunk.to_lowercase().cmp(&b_chunk.to_lowercase()),
+                }
+            }
+            (a_chunk, b_chunk) => return a_chunk.is_some().cmp(&b_chunk.is_some()),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Splits `s` into runs of digits and runs of everything else.
+fn chunks(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let is_digit = first.is_ascii_digit();
+        let len = rest
+            .find(|c: char| c.is_ascii_digit() != is_digit)
+            .unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(len);
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn natural_order() {
+        let mut names = vec![
+            "page10.jpg",
+            "page2.jpg",
+            "Page1.jpg",
+            "cover.jpg",
+            "page2b.jpg",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            vec![
+                "cover.jpg",
+                "Page1.jpg",
+                "page2.jpg",
+                "page2b.jpg",
+                "page10.jpg"
+            ],
+            names
+        );
+    }
+
+    #[test]
+    fn pages_and_info() -> Result<()> {
+        let info = "<ComicInfo><Title>The Title</Title><Series>Series</Series>\
+                    <Number>3</Number><Manga>YesAndRightToLeft</Manga></ComicInfo>";
+        let bytes = test_utils::build_zip(&[
+            ("Comic/10.png", b"ten"),
+            ("Comic/9.jpg", b"nine"),
+            ("__MACOSX/Comic/._9.jpg", b"fork"),
+            ("ComicInfo.xml", info.as_bytes()),
+        ]);
+        let mut comic = Comic::new(bytes)?;
+        assert_eq!(2, comic.page_count());
+        assert_eq!(Some("The Title".to_string()), comic.metadata().title);
+        assert_eq!(Direction::Rtl, comic.direction());
+        assert_eq!(
+            "<img src=\"data:image/jpeg;base64,bmluZQ==\" alt=\"Page 1\"/>",
+            comic.page(0)?
+        );
+        assert!(comic.next_chapter()?.contains("data:image/png;base64,dGVu"));
+        assert_eq!(1, comic.current_idx());
+        assert!(comic.next_chapter()?.contains("alt=\"Page 2\""));
+        assert_eq!(1, comic.current_idx());
+        assert!(comic.page(2).is_err());
+        comic.prev_chapter()?;
+        assert_eq!(0, comic.current_idx());
+        Ok(())
+    }
+
+    #[test]
+    fn series_title() -> Result<()> {
+        let xml = b"<ComicInfo><Series>Series</Series><Number>3</Number></ComicInfo>";
+        let info = ComicInfo::from_element(&parse_xml(&xml[..])?);
+        assert_eq!(Some("Series #3".to_string()), info.full_title());
+        assert_eq!(None, ComicInfo::default().full_title());
+        Ok(())
+    }
+}
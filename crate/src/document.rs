@@ -0,0 +1,235 @@
+//! What the reader needs from a book, whatever its format. EPUBs, and FictionBooks converted to
+//! EPUB, open as `Epub`s, plain text and Markdown as `TextDocument`s, and comic book archives as
+//! `Comic`s, whose pages are their chapters.
+
+use crate::comic::Comic;
+use crate::epub::{Epub, Page, Rendition, TocItem};
+use crate::error::{Error, Result};
+use crate::fb2;
+use crate::format::{self, Format};
+use crate::history::Location;
+use crate::layout::{Direction, LayoutProperties, WritingMode};
+use crate::progress::{Progress, TextLength};
+use crate::text::TextDocument;
+use minidom::Element;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    /// The book's language, when it says.
+    pub language: Option<String>,
+}
+
+pub trait Document {
+    fn metadata(&self) -> Metadata;
+
+    fn toc(&mut self) -> Result<Vec<TocItem>>;
+
+    fn chapter_count(&self) -> usize;
+
+    /// The index of the chapter the reader is at.
+    fn current_idx(&self) -> Result<usize>;
+
+    /// Moves to the chapter at `idx`, returning its markup.
+    fn chapter(&mut self, idx: usize) -> Result<String>;
+
+    /// Moves to the chapter `link` points at, relative to the current one.
+    fn chapter_by_link(&mut self, link: &str) -> Result<String>;
+
+    /// Moves to the chapter a link in the table of contents points at.
+    fn chapter_by_toc_link(&mut self, link: &str) -> Result<String> {
+        self.chapter_by_link(link)
+    }
+
+    /// Moves to the chapter that follows chapter `idx` when paging through the book. At the end
+    /// of the book it stays at `idx`, just like `chapter_before` does at the start.
+    fn chapter_after(&mut self, idx: usize) -> Result<String> {
+        let next_idx = if idx + 1 < self.chapter_count() {
+            idx + 1
+        } else {
+            idx
+        };
+        self.chapter(next_idx)
+    }
+
+    /// Moves to the chapter that precedes chapter `idx` when paging through the book, or stays
+    /// at `idx` at the start of the book.
+    fn chapter_before(&mut self, idx: usize) -> Result<String> {
+        self.chapter(idx.saturating_sub(1))
+    }
+
+    fn next_chapter(&mut self) -> Result<String> {
+        let current_idx = self.current_idx()?;
+        self.chapter_after(current_idx)
+    }
+
+    fn prev_chapter(&mut self) -> Result<String> {
+        let current_idx = self.current_idx()?;
+        self.chapter_before(current_idx)
+    }
+
+    /// Reads a file in the book, such as an image, by its path in the book.
+    fn resource(&mut self, path: &Path) -> Result<Vec<u8>>;
+
+    fn direction(&self) -> Direction;
+
+    fn writing_mode(&self) -> WritingMode {
+        WritingMode::HorizontalTb
+    }
+
+    /// How far into the book a position `within_item` (from 0 to 1) of the way through chapter
+    /// `item_idx` is. Unless the book knows better, chapters are taken to be the same length.
+    fn progress(&mut self, item_idx: usize, within_item: f64) -> Result<Progress> {
+        let length = TextLength { chars: 1, words: 0 };
+        let lengths = vec![length; self.chapter_count()];
+        Ok(Progress::new(&lengths, item_idx, within_item))
+    }
+
+    /// The reader's location, for books that keep a navigation history.
+    fn location(&self) -> Option<&Location> {
+        None
+    }
+
+    fn history_position(&self) -> usize {
+        0
+    }
+
+    /// Moves to entry `position` of the navigation history, returning its markup and location,
+    /// or nothing when there's no such entry.
+    fn go_to_history(&mut self, _position: usize) -> Result<Option<(String, Location)>> {
+        Ok(None)
+    }
+
+    /// Moves to `location` without adding to the navigation history.
+    fn go_to_location(&mut self, location: Location) -> Result<String> {
+        self.chapter(location.spine_idx)
+    }
+
+    /// Where the book opens.
+    fn start_location(&mut self) -> Location {
+        Location::new(0, None)
+    }
+
+    /// The footnote or endnote `link` points at, if it's one.
+    fn note(&mut self, _link: &str, _noteref: bool) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Whether chapters are page images, like a comic's, rather than text.
+    fn is_paged_images(&self) -> bool {
+        false
+    }
+
+    /// What the book has that only EPUBs have, if it's one.
+    fn epub_features(&mut self) -> Option<&mut dyn EpubFeatures> {
+        None
+    }
+}
+
+/// What only EPUBs have: CFIs, print page lists, renditions, fixed layouts and the content
+/// documents behind their chapters.
+pub trait EpubFeatures {
+    fn cfi(&mut self, location: &Location) -> Result<String>;
+
+    fn resolve_cfi(&mut self, cfi: &str) -> Result<Location>;
+
+    fn page_list(&mut self) -> Result<&[Page]>;
+
+    fn chapter_by_page(&mut self, page: &Page) -> Result<String>;
+
+    /// The label of the print page the reader is on, given whether the start of a page marked
+    /// by a fragment `is_passed`.
+    fn current_page(&mut self, is_passed: &dyn Fn(&str) -> bool) -> Result<Option<String>>;
+
+    fn renditions(&self) -> &[Rendition];
+
+    /// Reopens the book as rendition `idx`.
+    fn select_rendition(&mut self, idx: usize) -> Result<()>;
+
+    fn layout(&self, idx: usize) -> Result<LayoutProperties>;
+
+    /// The first and second chapters of the two-page spread chapter `idx` is in, if any.
+    fn spread(&self, idx: usize) -> Result<Option<(usize, usize)>>;
+
+    /// The size fixed-layout chapter `idx` is designed for.
+    fn viewport(&mut self, idx: usize) -> Result<Option<(u32, u32)>>;
+
+    /// The markup of chapter `idx`, without moving to it.
+    fn spine_markup(&mut self, idx: usize) -> Result<String>;
+
+    /// Chapter `idx` as read for `spine_markup`, along with the path the links in it are
+    /// relative to.
+    fn spine_doc(&mut self, idx: usize) -> Result<(Element, PathBuf)>;
+
+    /// The path the links in the table of contents are relative to.
+    fn toc_path(&self) -> &Path;
+}
+
+/// Opens a book in any of the formats the reader supports, going by what's in `bytes`.
+pub fn open(bytes: Vec<u8>) -> Result<Box<dyn Document>> {
+    let bytes = match format::detect(&bytes) {
+        Format::Epub | Format::BrokenEpub => bytes,
+        Format::Fb2 => fb2::to_epub(&bytes).map_err(|e| conversion_error(Format::Fb2, e))?,
+        Format::Text => return Ok(Box::new(TextDocument::new(&bytes))),
+        Format::Cbz => return Ok(Box::new(Comic::new(bytes)?)),
+        other => return Err(Error::FormatError(other)),
+    };
+    Ok(Box::new(Epub::new(bytes)?))
+}
+
+fn conversion_error(format: Format, err: Error) -> Error {
+    Error::ConversionError(format, Box::new(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn open_formats() -> Result<()> {
+        let epub = test_utils::build_single_chapter_epub("<html><body>One</body></html>");
+        let mut document = open(epub)?;
+        assert!(document.epub_features().is_some());
+        assert_eq!(1, document.chapter_count());
+        assert!(document.chapter(0)?.contains("One"));
+        assert!(document.next_chapter()?.contains("One"));
+        let expected = Metadata {
+            title: Some("Test".to_string()),
+            authors: vec![],
+            language: Some("en".to_string()),
+        };
+        assert_eq!(expected, document.metadata());
+
+        let mut document = open(b"Walden\n\nby Henry David Thoreau\n".to_vec())?;
+        assert!(document.epub_features().is_none());
+        assert!(!document.is_paged_images());
+        assert_eq!(Some("Walden".to_string()), document.metadata().title);
+
+        let cbz = test_utils::build_zip(&[("1.png", b"one"), ("2.png", b"two")]);
+        let mut document = open(cbz)?;
+        assert!(document.epub_features().is_none());
+        assert!(document.is_paged_images());
+        assert!(document.toc()?.is_empty());
+        assert_eq!(2, document.chapter_count());
+        assert!(document.next_chapter()?.contains("alt=\"Page 2\""));
+        assert_eq!(1, document.current_idx()?);
+        assert!(document.chapter_after(1)?.contains("alt=\"Page 2\""));
+        assert!((document.progress(1, 1.0)?.fraction - 1.0).abs() < 1e-9);
+        assert!(document.location().is_none());
+        assert_eq!(b"one".to_vec(), document.resource(Path::new("1.png"))?);
+
+        match open(b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec()) {
+            Err(Error::FormatError(Format::Unknown)) => (),
+            _ => panic!("expected a format error"),
+        }
+        let fb2 = b"<?xml version=\"1.0\"?>\n<FictionBook><description/></FictionBook>";
+        match open(fb2.to_vec()) {
+            Err(Error::ConversionError(Format::Fb2, _)) => (),
+            _ => panic!("expected a conversion error"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+const SNIFF_LEN: usize = 1024;
+/// Characters Windows-1252 maps to the 0x80-0x9F range, which Latin-1 leaves as C1 controls.
+/// Undefined code points are kept as their C1 counterpart, like browsers do.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+/// Characters Windows-1251 maps the 0x80-0xFF range to, mostly Cyrillic.
+const WINDOWS_1251_HIGH: [char; 128] = [
+    '\u{402}', '\u{403}', '\u{201a}', '\u{453}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{20ac}', '\u{2030}', '\u{409}', '\u{2039}', '\u{40a}', '\u{40c}', '\u{40b}', '\u{40f}',
+    '\u{452}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{98}', '\u{2122}', '\u{459}', '\u{203a}', '\u{45a}', '\u{45c}', '\u{45b}', '\u{45f}',
+    '\u{a0}', '\u{40e}', '\u{45e}', '\u{408}', '\u{a4}', '\u{490}', '\u{a6}', '\u{a7}', '\u{401}',
+    '\u{a9}', '\u{404}', '\u{ab}', '\u{ac}', '\u{ad}', '\u{ae}', '\u{407}', '\u{b0}', '\u{b1}',
+    '\u{406}', '\u{456}', '\u{491}', '\u{b5}', '\u{b6}', '\u{b7}', '\u{451}', '\u{2116}',
+    '\u{454}', '\u{bb}', '\u{458}', '\u{405}', '\u{455}', '\u{457}', '\u{410}', '\u{411}',
+    '\u{412}', '\u{413}', '\u{414}', '\u{415}', '\u{416}', '\u{417}', '\u{418}', '\u{419}',
+    '\u{41a}', '\u{41b}', '\u{41c}', '\u{41d}', '\u{41e}', '\u{41f}', '\u{420}', '\u{421}',
+    '\u{422}', '\u{423}', '\u{424}', '\u{425}', '\u{426}', '\u{427}', '\u{428}', '\u{429}',
+    '\u{42a}', '\u{42b}', '\u{42c}', '\u{42d}', '\u{42e}', '\u{42f}', '\u{430}', '\u{431}',
+    '\u{432}', '\u{433}', '\u{434}', '\u{435}', '\u{436}', '\u{437}', '\u{438}', '\u{439}',
+    '\u{43a}', '\u{43b}', '\u{43c}', '\u{43d}', '\u{43e}', '\u{43f}', '\u{440}', '\u{441}',
+    '\u{442}', '\u{443}', '\u{444}', '\u{445}', '\u{446}', '\u{447}', '\u{448}', '\u{449}',
+    '\u{44a}', '\u{44b}', '\u{44c}', '\u{44d}', '\u{44e}', '\u{44f}',
+];
+/// Characters KOI8-R maps the 0x80-0xFF range to, box drawing and Cyrillic.
+const KOI8_R_HIGH: [char; 128] = [
+    '\u{2500}', '\u{2502}', '\u{250c}', '\u{2510}', '\u{2514}', '\u{2518}', '\u{251c}', '\u{2524}',
+    '\u{252c}', '\u{2534}', '\u{253c}', '\u{2580}', '\u{2584}', '\u{2588}', '\u{258c}', '\u{2590}',
+    '\u{2591}', '\u{2592}', '\u{2593}', '\u{2320}', '\u{25a0}', '\u{2219}', '\u{221a}', '\u{2248}',
+    '\u{2264}', '\u{2265}', '\u{a0}', '\u{2321}', '\u{b0}', '\u{b2}', '\u{b7}', '\u{f7}',
+    '\u{2550}', '\u{2551}', '\u{2552}', '\u{451}', '\u{2553}', '\u{2554}', '\u{2555}', '\u{2556}',
+    '\u{2557}', '\u{2558}', '\u{2559}', '\u{255a}', '\u{255b}', '\u{255c}', '\u{255d}', '\u{255e}',
+    '\u{255f}', '\u{2560}', '\u{2561}', '\u{401}', '\u{2562}', '\u{2563}', '\u{2564}', '\u{2565}',
+    '\u{2566}', '\u{2567}', '\u{2568}', '\u{2569}', '\u{256a}', '\u{256b}', '\u{256c}', '\u{a9}',
+    '\u{44e}', '\u{430}', '\u{431}', '\u{446}', '\u{434}', '\u{435}', '\u{444}', '\u{433}',
+    '\u{445}', '\u{438}', '\u{439}', '\u{43a}', '\u{43b}', '\u{43c}', '\u{43d}', '\u{43e}',
+    '\u{43f}', '\u{44f}', '\u{440}', '\u{441}', '\u{442}', '\u{443}', '\u{436}', '\u{432}',
+    '\u{44c}', '\u{44b}', '\u{437}', '\u{448}', '\u{44d}', '\u{449}', '\u{447}', '\u{44a}',
+    '\u{42e}', '\u{410}', '\u{411}', '\u{426}', '\u{414}', '\u{415}', '\u{424}', '\u{413}',
+    '\u{425}', '\u{418}', '\u{419}', '\u{41a}', '\u{41b}', '\u{41c}', '\u{41d}', '\u{41e}',
+    '\u{41f}', '\u{42f}', '\u{420}', '\u{421}', '\u{422}', '\u{423}', '\u{416}', '\u{412}',
+    '\u{42c}', '\u{42b}', '\u{417}', '\u{428}', '\u{42d}', '\u{429}', '\u{427}', '\u{42a}',
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Windows1252,
+    Windows1251,
+    Koi8R,
+}
+
+/// Decodes an XML or HTML document into UTF-8, honoring its byte order mark, its XML declaration
+/// or its `<meta charset>`, in that order. Documents without any of those, or declaring an
+/// encoding that isn't supported, are assumed to be UTF-8, falling back to Windows-1252 when they
+/// turn out not to be.
+pub fn decode(bytes: &[u8]) -> String {
+    let (encoding, bom_len) = detect(bytes);
+    let bytes = &bytes[bom_len..];
+    match encoding {
+        Encoding::Utf8 => match String::from_utf8(bytes.to_vec()) {
+            Ok(text) => text,
+            Err(_) => decode_single_byte(bytes, Encoding::Windows1252),
+        },
+        Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+        Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+        Encoding::Latin1 | Encoding::Windows1252 | Encoding::Windows1251 | Encoding::Koi8R => {
+            decode_single_byte(bytes, encoding)
+        }
+    }
+}
+
+/// The encoding a document declares, when `decode` doesn't support it and has to guess instead.
+pub fn unsupported_label(bytes: &[u8]) -> Option<String> {
+    let head = sniffed_head(bytes);
+    declared_label(&head)
+        .filter(|label| from_label(label, bytes).is_none())
+        .map(|label| label.to_string())
+}
+
+fn detect(bytes: &[u8]) -> (Encoding, usize) {
+    if bytes.starts_with(&[0xef, 0xbb, 0xbf]) {
+        return (Encoding::Utf8, 3);
+    }
+    if bytes.starts_with(&[0xff, 0xfe]) {
+        return (Encoding::Utf16Le, 2);
+    }
+    if bytes.starts_with(&[0xfe, 0xff]) {
+        return (Encoding::Utf16Be, 2);
+    }
+    // `<?` without a byte order mark
+    if bytes.starts_with(&[0x3c, 0x00, 0x3f, 0x00]) {
+        return (Encoding::Utf16Le, 0);
+    }
+    if bytes.starts_with(&[0x00, 0x3c, 0x00, 0x3f]) {
+        return (Encoding::Utf16Be, 0);
+    }
+    let head = sniffed_head(bytes);
+    let encoding = declared_label(&head)
+        .and_then(|label| from_label(label, bytes))
+        .unwrap_or(Encoding::Utf8);
+    (encoding, 0)
+}
+
+fn sniffed_head(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(&bytes[..bytes.len().min(SNIFF_LEN)]).to_lowercase()
+}
+
+fn from_label(label: &str, bytes: &[u8]) -> Option<Encoding> {
+    let encoding = match label {
+        "utf-8" | "utf8" => Encoding::Utf8,
+        // a UTF-16 document that made it this far has no BOM, so guess from the first `<`
+        "utf-16" | "utf-16le" | "utf-16be" if bytes.first() == Some(&0) => Encoding::Utf16Be,
+        "utf-16" | "utf-16le" | "utf-16be" => Encoding::Utf16Le,
+        "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "l1" => Encoding::Latin1,
+        "windows-1252" | "cp1252" | "us-ascii" | "ascii" => Encoding::Windows1252,
+        "windows-1251" | "cp1251" | "x-cp1251" => Encoding::Windows1251,
+        "koi8-r" | "koi8r" | "koi8_r" | "koi8" | "koi" | "cskoi8r" => Encoding::Koi8R,
+        _ => return None,
+    };
+    Some(encoding)
+}
+
+fn declared_label(head: &str) -> Option<&str> {
+    if head.trim_start().starts_with("<?xml") {
+        let decl_end = head.find("?>").unwrap_or(head.len());
+        if let Some(idx) = head[..decl_end].find("encoding") {
+            let value = head[idx + "encoding".len()..decl_end]
+                .trim_start()
+                .trim_start_matches('=')
+                .trim_start();
+            let quotes: &[char] = &['"', '\''];
+            return value.get(1..).and_then(|v| v.split(quotes).next());
+        }
+    }
+    // only `<meta>` tags count, not a `charset=` that happens to be in the text
+    let mut rest = head;
+    while let Some(idx) = rest.find("<meta") {
+        rest = &rest[idx + "<meta".len()..];
+        let tag = &rest[..rest.find('>').unwrap_or(rest.len())];
+        if let Some(idx) = tag.find("charset=") {
+            return tag[idx + "charset=".len()..]
+                .trim_start_matches(&['"', '\''][..])
+                .split(|c: char| c == '"' || c == '\'' || c == ';' || c == '/' || c.is_whitespace())
+                .next()
+                .filter(|label| !label.is_empty());
+        }
+    }
+    None
+}
+
+fn decode_utf16(bytes: &[u8], to_u16: fn([u8; 2]) -> u16) -> String {
+    let units: Vec<u16> = bytes
+        .chunks(2)
+        .filter(|c| c.len() == 2)
+        .map(|c| to_u16([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn decode_single_byte(bytes: &[u8], encoding: Encoding) -> String {
+    bytes
+        .iter()
+        .map(|&b| match (encoding, b) {
+            (Encoding::Windows1252, 0x80..=0x9f) => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+            (Encoding::Windows1251, 0x80..=0xff) => WINDOWS_1251_HIGH[(b - 0x80) as usize],
+            (Encoding::Koi8R, 0x80..=0xff) => KOI8_R_HIGH[(b - 0x80) as usize],
+            _ => b as char,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_with_bom() {
+        assert_eq!("<p>ñ</p>", decode(b"\xef\xbb\xbf<p>\xc3\xb1</p>"));
+    }
+
+    #[test]
+    fn utf16() {
+        let le: Vec<u8> = "\u{feff}<p>ñ</p>"
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes().to_vec())
+            .collect();
+        let be: Vec<u8> = "<?xml version=\"1.0\" encoding=\"UTF-16\"?><p>ñ</p>"
+            .encode_utf16()
+            .flat_map(|u| u.to_be_bytes().to_vec())
+            .collect();
+        assert_eq!("<p>ñ</p>", decode(&le));
+        assert!(decode(&be).ends_with("<p>ñ</p>"));
+    }
+
+    #[test]
+    fn declared_single_byte_encodings() {
+        let latin1 = b"<?xml version='1.0' encoding='ISO-8859-1'?><p>\xf1</p>";
+        let windows_1252 = b"<?xml version=\"1.0\" encoding=\"windows-1252\"?><p>\x93\xf1\x94</p>";
+        let meta = b"<html><head><meta charset=\"windows-1252\"></head><p>\x85</p></html>";
+        assert!(decode(latin1).ends_with("<p>ñ</p>"));
+        assert!(decode(windows_1252).ends_with("<p>“ñ”</p>"));
+        assert!(decode(meta).ends_with("<p>…</p></html>"));
+    }
+
+    #[test]
+    fn declared_cyrillic_encodings() {
+        let windows_1251 = b"<?xml version=\"1.0\" encoding=\"windows-1251\"?><p>\xcc\xe8\xf0</p>";
+        let koi8_r = b"<?xml version=\"1.0\" encoding=\"koi8-r\"?><p>\xed\xc9\xd2</p>";
+        assert!(decode(windows_1251).ends_with("<p>Мир</p>"));
+        assert!(decode(koi8_r).ends_with("<p>Мир</p>"));
+    }
+
+    #[test]
+    fn invalid_utf8_falls_back_to_windows_1252() {
+        assert_eq!("<p>ñ</p>", decode(b"<p>\xf1</p>"));
+    }
+
+    #[test]
+    fn charset_only_in_meta() {
+        let text = b"<html><body><p>charset=iso-8859-1 \xc3\xb1</p></body></html>";
+        let http_equiv = b"<meta http-equiv='content-type' content='text/html; charset=l1'>\xf1";
+        assert_eq!(None, declared_label(&sniffed_head(text)));
+        assert!(decode(text).ends_with("<p>charset=iso-8859-1 ñ</p></body></html>"));
+        assert!(decode(http_equiv).ends_with("'>ñ"));
+    }
+
+    #[test]
+    fn unsupported_encoding() {
+        let ebcdic = b"<?xml version=\"1.0\" encoding=\"EBCDIC\"?><p>\xf1</p>";
+        assert_eq!(Some("ebcdic".to_string()), unsupported_label(ebcdic));
+        assert!(decode(ebcdic).ends_with("<p>ñ</p>"));
+        assert_eq!(
+            None,
+            unsupported_label(b"<?xml version='1.0' encoding='utf-8'?>")
+        );
+    }
+}
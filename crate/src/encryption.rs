@@ -0,0 +1,267 @@
+//! `META-INF/encryption.xml`, which lists the resources that can't be used as they are stored,
+//! the two font obfuscation algorithms books use to tie embedded fonts to them, and the DRM
+//! schemes anything else is encrypted with.
+
+use crate::error::Result;
+use crate::xml::Descend;
+use minidom::Element;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use url::percent_encoding::percent_decode;
+
+pub const IDPF_OBFUSCATION: &str = "http://www.idpf.org/2008/embedding";
+pub const ADOBE_OBFUSCATION: &str = "http://ns.adobe.com/pdf/enc#RC";
+const IDPF_OBFUSCATED_LEN: usize = 1040;
+const ADOBE_OBFUSCATED_LEN: usize = 1024;
+const FAIRPLAY_ALGORITHM: &str = "http://itunes.apple.com/dataenc";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protection {
+    AdobeAdept,
+    AppleFairPlay,
+    ReadiumLcp,
+    Unknown,
+}
+
+impl fmt::Display for Protection {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Protection::AdobeAdept => "Adobe DRM",
+            Protection::AppleFairPlay => "Apple FairPlay",
+            Protection::ReadiumLcp => "Readium LCP",
+            Protection::Unknown => "DRM",
+        };
+        write!(fmt, "{}", name)
+    }
+}
+
+/// Maps the path of every encrypted resource to the URI of the algorithm it's encrypted with.
+pub fn parse_encryption(doc: &Element) -> HashMap<PathBuf, String> {
+    let mut encryption = HashMap::new();
+    for encrypted_data in doc.descendants().filter(|e| e.name() == "EncryptedData") {
+        let algorithm = encrypted_data
+            .children()
+            .find(|c| c.name() == "EncryptionMethod")
+            .and_then(|m| m.attr("Algorithm"));
+        let uri = encrypted_data
+            .descendants()
+            .find(|c| c.name() == "CipherReference")
+            .and_then(|r| r.attr("URI"));
+        if let (Some(algorithm), Some(uri)) = (algorithm, uri) {
+            let path = percent_decode(uri.as_bytes()).decode_utf8_lossy();
+            encryption.insert(PathBuf::from(path.as_ref()), algorithm.to_string());
+        }
+    }
+    encryption
+}
+
+/// Identifies the DRM scheme of a book that has resources encrypted with something other than
+/// font obfuscation. Each scheme leaves its own file in `META-INF`, which `has_file` looks for.
+pub fn protection<F>(encryption: &HashMap<PathBuf, String>, mut has_file: F) -> Option<Protection>
+where
+    F: FnMut(&str) -> bool,
+{
+    let mut algorithms = encryption
+        .values()
+        .filter(|a| *a != IDPF_OBFUSCATION && *a != ADOBE_OBFUSCATION)
+        .peekable();
+    algorithms.peek()?;
+    let is_fairplay = algorithms.any(|a| a == FAIRPLAY_ALGORITHM);
+    let protection = if is_fairplay || has_file("META-INF/sinf.xml") {
+        Protection::AppleFairPlay
+    } else if has_file("META-INF/license.lcpl") {
+        Protection::ReadiumLcp
+    } else if has_file("META-INF/rights.xml") {
+        Protection::AdobeAdept
+    } else {
+        Protection::Unknown
+    };
+    Some(protection)
+}
+
+/// Undoes font obfuscation in place. `identifiers` are the book's `dc:identifier`s, starting
+/// with its unique identifier, which the IDPF algorithm derives its key from. The Adobe
+/// algorithm uses the first one that is a UUID instead.
+pub fn deobfuscate(bytes: &mut [u8], algorithm: &str, identifiers: &[String]) -> Result<()> {
+    match algorithm {
+        IDPF_OBFUSCATION => {
+            let identifier: String = identifiers
+                .first()
+                .ok_or("no unique identifier to deobfuscate with")?
+                .chars()
+                .filter(|c| !" \t\r\n".contains(*c))
+                .collect();
+            xor(bytes, &sha1(identifier.as_bytes()), IDPF_OBFUSCATED_LEN);
+        }
+        ADOBE_OBFUSCATION => {
+            let key = identifiers
+                .iter()
+                .filter_map(|i| uuid_bytes(i))
+                .next()
+                .ok_or("no UUID identifier to deobfuscate with")?;
+            xor(bytes, &key, ADOBE_OBFUSCATED_LEN);
+        }
+        _ => return Err("unsupported encryption algorithm".into()),
+    }
+    Ok(())
+}
+
+fn xor(bytes: &mut [u8], key: &[u8], len: usize) {
+    for (byte, key_byte) in bytes.iter_mut().take(len).zip(key.iter().cycle()) {
+        *byte ^= key_byte;
+    }
+}
+
+fn uuid_bytes(identifier: &str) -> Option<Vec<u8>> {
+    let hex: String = identifier
+        .trim()
+        .trim_start_matches("urn:uuid:")
+        .chars()
+        .filter(|c| *c != '-')
+        .collect();
+    if hex.len() != 32 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..16)
+        .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok())
+        .collect()
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [
+        0x6745_2301,
+        0xefcd_ab89,
+        0x98ba_dcfe,
+        0x1032_5476,
+        0xc3d2_e1f0,
+    ];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (s, v) in state.iter_mut().zip(&[a, b, c, d, e]) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+    let mut digest = [0; 20];
+    for (chunk, s) in digest.chunks_mut(4).zip(&state) {
+        chunk.copy_from_slice(&s.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xml::parse_xml;
+
+    #[test]
+    fn sha1_digest() {
+        let digest: String = sha1(b"abc").iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d", digest);
+        assert_eq!(sha1(&[b'a'; 64])[..4], [0x00, 0x98, 0xba, 0x82]);
+    }
+
+    #[test]
+    fn encryption_xml() -> Result<()> {
+        let doc = parse_xml(
+            &br#"<encryption xmlns="urn:oasis:names:tc:opendocument:xmlns:container"
+                xmlns:enc="http://www.w3.org/2001/04/xmlenc#">
+              <enc:EncryptedData>
+                <enc:EncryptionMethod Algorithm="http://www.idpf.org/2008/embedding"/>
+                <enc:CipherData>
+                  <enc:CipherReference URI="OEBPS/fonts/My%20Font.otf"/>
+                </enc:CipherData>
+              </enc:EncryptedData>
+            </encryption>"#[..],
+        )?;
+        let encryption = parse_encryption(&doc);
+        assert_eq!(
+            Some(&IDPF_OBFUSCATION.to_string()),
+            encryption.get(&PathBuf::from("OEBPS/fonts/My Font.otf"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn drm_protection() {
+        let mut encryption = HashMap::new();
+        encryption.insert(
+            PathBuf::from("OEBPS/fonts/font.otf"),
+            IDPF_OBFUSCATION.to_string(),
+        );
+        assert_eq!(None, protection(&encryption, |_| true));
+        encryption.insert(
+            PathBuf::from("OEBPS/chapter1.xhtml"),
+            "http://www.w3.org/2001/04/xmlenc#aes128-cbc".to_string(),
+        );
+        let lcp = protection(&encryption, |n| n == "META-INF/license.lcpl");
+        assert_eq!(Some(Protection::ReadiumLcp), lcp);
+        let adept = protection(&encryption, |n| n == "META-INF/rights.xml");
+        assert_eq!(Some(Protection::AdobeAdept), adept);
+        assert_eq!(
+            Some(Protection::Unknown),
+            protection(&encryption, |_| false)
+        );
+        encryption.insert(
+            PathBuf::from("OEBPS/chapter2.xhtml"),
+            FAIRPLAY_ALGORITHM.to_string(),
+        );
+        let fairplay = protection(&encryption, |_| false);
+        assert_eq!(Some(Protection::AppleFairPlay), fairplay);
+    }
+
+    #[test]
+    fn obfuscation_round_trip() -> Result<()> {
+        let font: Vec<u8> = (0..2000).map(|i| (i % 251) as u8).collect();
+        let identifiers = vec![
+            " urn:isbn:9780000000000\n".to_string(),
+            "urn:uuid:12345678-1234-1234-1234-123456789abc".to_string(),
+        ];
+        for algorithm in &[IDPF_OBFUSCATION, ADOBE_OBFUSCATION] {
+            let mut bytes = font.clone();
+            deobfuscate(&mut bytes, algorithm, &identifiers)?;
+            assert_ne!(font[..10], bytes[..10]);
+            assert_eq!(font[1040..], bytes[1040..]);
+            deobfuscate(&mut bytes, algorithm, &identifiers)?;
+            assert_eq!(font, bytes);
+        }
+        let mut bytes = font.clone();
+        deobfuscate(&mut bytes, ADOBE_OBFUSCATION, &identifiers)?;
+        assert_eq!(font[0] ^ 0x12, bytes[0]);
+        assert_eq!(font[1023] ^ 0xbc, bytes[1023]);
+        assert_eq!(font[1024], bytes[1024]);
+        Ok(())
+    }
+}
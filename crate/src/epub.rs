@@ -1,23 +1,54 @@
-use crate::error::Result;
+use crate::cfi::{Cfi, Step};
+use crate::document::{Document, EpubFeatures, Metadata};
+use crate::encryption;
+use crate::error::{Error, Result};
+use crate::format;
+use crate::history::{History, Location};
+use crate::html::parse_html;
+use crate::layout::{self, Direction, Layout, LayoutProperties, PageSpread, WritingMode};
+use crate::progress::{Progress, TextLength};
+use crate::sanitize::Sanitizer;
 use crate::utils;
-use crate::xml::{parse_xml, Descend};
+use crate::xml::{parse_xml, text_content, to_markup, Descend};
 use minidom::Element;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{BufReader, Cursor, Read};
 use std::path::{Path, PathBuf};
+use url::percent_encoding::percent_decode;
 use zip::ZipArchive;
 
 type ItemId = String;
-type Spine = Vec<String>;
+type Spine = Vec<SpineItem>;
 type Zip = ZipArchive<Cursor<Vec<u8>>>;
 type Manifest = HashMap<ItemId, ManifestItem>;
 type Toc = Vec<TocItem>;
 
+/// Media types a spine item may have without a fallback. `text/html` isn't a core media type,
+/// but it's common enough in the wild and the tag soup parser handles it.
+const CONTENT_DOC_TYPES: &[&str] = &["application/xhtml+xml", "image/svg+xml", "text/html"];
+const CORE_IMAGE_TYPES: &[&str] = &[
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/svg+xml",
+    "image/webp",
+];
+const NOTE_TYPES: &[&str] = &["footnote", "endnote", "rearnote", "note"];
+
 #[derive(Debug)]
 struct ManifestItem {
     id: ItemId,
     href: String,
     media_type: String,
+    fallback: Option<ItemId>,
+    properties: Vec<String>,
+}
+
+#[derive(Debug)]
+struct SpineItem {
+    idref: ItemId,
+    linear: bool,
+    properties: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -26,30 +57,115 @@ pub struct TocItem {
     pub href: String,
 }
 
+/// A print page from the book's page list, pointing at where it starts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Page {
+    pub label: String,
+    pub path: PathBuf,
+    pub fragment: Option<String>,
+    /// The spine item the page starts in, if it's in the spine.
+    pub spine_idx: Option<usize>,
+}
+
+impl Page {
+    fn new(label: &str, href: &str, relative_to: &Path) -> Result<Page> {
+        let (path, fragment) = split_href(href, relative_to)?;
+        let label = label.trim().to_string();
+        Ok(Page {
+            label,
+            path,
+            fragment,
+            spine_idx: None,
+        })
+    }
+}
+
+/// A structural point in the book, such as its cover or where the main content starts. Kinds
+/// come from the EPUB 3 structural semantics vocabulary, e.g. `bodymatter` or `toc`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Landmark {
+    pub kind: String,
+    pub title: String,
+    pub path: PathBuf,
+    pub fragment: Option<String>,
+}
+
+impl Landmark {
+    fn new(kind: &str, title: &str, href: &str, relative_to: &Path) -> Result<Landmark> {
+        let (path, fragment) = split_href(href, relative_to)?;
+        Ok(Landmark {
+            kind: kind.to_string(),
+            title: title.trim().to_string(),
+            path,
+            fragment,
+        })
+    }
+}
+
+/// A rootfile in container.xml along with its Multiple-Rendition selection attributes.
+#[derive(Clone, Debug)]
+pub struct Rendition {
+    pub full_path: String,
+    pub media_type: String,
+    pub access_mode: Option<String>,
+    pub label: Option<String>,
+    pub language: Option<String>,
+    pub layout: Option<String>,
+    pub media: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct Epub {
     current_path: PathBuf,
+    direction: Direction,
+    /// Resources listed in encryption.xml, with the algorithm each one is encrypted with.
+    encryption: HashMap<PathBuf, String>,
+    history: History,
+    /// The book's `dc:identifier`s, starting with its unique identifier.
+    identifiers: Vec<String>,
+    /// The last content document read by `sanitized_doc`, with its path.
+    last_doc: Option<(PathBuf, Element)>,
+    layout: LayoutProperties,
     manifest: Manifest,
+    nav_path: Option<PathBuf>,
     opf_doc: Element,
     opf_path: PathBuf,
+    page_list: Option<Vec<Page>>,
+    renditions: Vec<Rendition>,
+    sanitizer: Sanitizer,
     spine: Spine,
+    /// The text lengths of the spine items, once progress is asked for.
+    text_lengths: Option<Vec<TextLength>>,
     toc_path: PathBuf,
+    writing_mode: WritingMode,
     zip: Zip,
 }
 
 impl Epub {
-    // TODO: clean up. Implement &[u8] constructor.
     pub fn new(bytes: Vec<u8>) -> Result<Epub> {
+        Epub::with_rendition(bytes, 0)
+    }
+
+    // TODO: clean up. Implement &[u8] constructor.
+    pub fn with_rendition(bytes: Vec<u8>, rendition_idx: usize) -> Result<Epub> {
+        match format::detect(&bytes) {
+            format if format.is_epub() => (),
+            other => return Err(Error::FormatError(other)),
+        }
         let mut zip = ZipArchive::new(Cursor::new(bytes))?;
+        let encryption = match zip.by_name("META-INF/encryption.xml") {
+            Ok(encryption_file) => encryption::parse_encryption(&parse_xml(encryption_file)?),
+            Err(_) => HashMap::new(),
+        };
+        if let Some(protection) = encryption::protection(&encryption, |n| zip.by_name(n).is_ok()) {
+            return Err(Error::DrmError(protection));
+        }
         let container_doc = parse_xml(zip.by_name("META-INF/container.xml")?)?;
-        let rootfile_node = container_doc
-            .descendants()
-            .find(|n| n.name() == "rootfile")
-            .ok_or("no rootfile in container.xml")?;
-        let opf_str_path = rootfile_node
-            .attr("full-path")
-            .ok_or("no full-path attribute in rootfile")?;
-        let opf_file = zip.by_name(opf_str_path)?;
+        let renditions = read_renditions(&container_doc)?;
+        let rendition = renditions
+            .get(rendition_idx)
+            .ok_or("rendition_idx not in container.xml")?;
+        let opf_file = zip.by_name(&rendition.full_path)?;
         let opf_path = opf_file.sanitized_name();
         let opf_doc = parse_xml(opf_file)?;
         let manifest_node = opf_doc
@@ -66,12 +182,16 @@ impl Epub {
                     .attr("media-type")
                     .expect("media_type missing in item")
                     .to_string();
+                let fallback = i.attr("fallback").map(String::from);
+                let properties = split_properties(i.attr("properties"));
                 (
                     id.clone(),
                     ManifestItem {
                         id,
                         href,
                         media_type,
+                        fallback,
+                        properties,
                     },
                 )
             })
@@ -83,49 +203,377 @@ impl Epub {
         let spine = spine_node
             .children()
             .filter(|c| c.name() == "itemref")
-            .map(|i| i.attr("idref").expect("idref missing in item").to_string())
+            .map(|i| SpineItem {
+                idref: i.attr("idref").expect("idref missing in item").to_string(),
+                linear: i.attr("linear") != Some("no"),
+                properties: split_properties(i.attr("properties")),
+            })
             .collect();
+        let mut identifiers = vec![];
+        let mut layout = LayoutProperties::default();
+        let mut writing_mode = None;
+        let unique_identifier = opf_doc.attr("unique-identifier");
+        let metadata_node = opf_doc.children().find(|n| n.name() == "metadata");
+        for meta in metadata_node.iter().flat_map(|n| n.children()) {
+            if meta.name() == "identifier" {
+                let identifier = meta.text().trim().to_string();
+                if unique_identifier.is_some() && meta.attr("id") == unique_identifier {
+                    identifiers.insert(0, identifier);
+                } else {
+                    identifiers.push(identifier);
+                }
+                continue;
+            }
+            match (meta.attr("property"), meta.attr("name")) {
+                (Some(property), _) if property.starts_with("rendition:") => {
+                    layout.set_meta(property, &meta.text())
+                }
+                (_, Some("primary-writing-mode")) => {
+                    writing_mode = meta.attr("content").and_then(WritingMode::from_value)
+                }
+                _ => (),
+            }
+        }
+        if writing_mode.is_none() {
+            writing_mode = stylesheets_writing_mode(&mut zip, &manifest, &spine, &opf_path);
+        }
+        let writing_mode = writing_mode.unwrap_or(WritingMode::HorizontalTb);
+        let direction = Direction::from_attr(spine_node.attr("page-progression-direction"))
+            .unwrap_or_else(|| writing_mode.direction());
         let toc_id = spine_node.attr("toc").ok_or("toc missing in spine")?;
         let toc_item = manifest
             .get(toc_id)
             .ok_or("toc in spine not defined in manifest")?;
         let toc_path = resolve_path(&toc_item.href, &opf_path);
+        let nav_path = manifest
+            .values()
+            .find(|i| i.properties.iter().any(|p| p == "nav"))
+            .map(|i| resolve_path(&i.href, &opf_path));
         Ok(Epub {
             current_path: opf_path.clone(),
+            direction,
+            encryption,
+            history: History::default(),
+            identifiers,
+            last_doc: None,
+            layout,
             manifest,
+            nav_path,
             opf_doc,
             opf_path,
+            page_list: None,
+            renditions,
+            sanitizer: Sanitizer::default(),
             spine,
+            text_lengths: None,
             toc_path,
+            writing_mode,
             zip,
         })
     }
 
+    /// Lists the renditions declared in container.xml, in order. The first one is the default.
+    pub fn renditions(&self) -> &[Rendition] {
+        &self.renditions
+    }
+
+    /// Reopens the book using another of its renditions, at its start. The book is left as it
+    /// was when the rendition can't be opened.
+    pub fn select_rendition(&mut self, rendition_idx: usize) -> Result<()> {
+        let bytes = self.zip.clone().into_inner().into_inner();
+        let mut epub = Epub::with_rendition(bytes, rendition_idx)?;
+        epub.sanitizer = self.sanitizer.clone();
+        *self = epub;
+        Ok(())
+    }
+
+    /// Replaces the sanitizer that chapters go through before being returned.
+    #[allow(dead_code)]
+    pub fn set_sanitizer(&mut self, sanitizer: Sanitizer) {
+        self.sanitizer = sanitizer;
+        self.last_doc = None;
+    }
+
     #[allow(dead_code)]
     pub fn doc_count(&self) -> Result<usize> {
         Ok(self.spine.len())
     }
 
     pub fn chapter(&mut self, item_idx: usize) -> Result<String> {
-        let idref = self.spine.get(item_idx).ok_or("item_idx not in spine")?;
-        let item = self.manifest.get(idref).ok_or("idref not in manifest")?;
-        self.current_chapter(&item.href.clone(), &self.opf_path.clone())
+        self.go_to_location(Location::new(item_idx, None))
+    }
+
+    /// Reads spine item `item_idx` the way chapters are returned, without moving to it. Also
+    /// returns the item's path, which the links in it are relative to.
+    pub fn spine_doc(&mut self, item_idx: usize) -> Result<(Element, PathBuf)> {
+        let path = self.spine_item_path(item_idx)?;
+        Ok((self.prepared_doc(&path)?, path))
+    }
+
+    /// Moves to `location` without adding to the navigation history.
+    pub fn go_to_location(&mut self, location: Location) -> Result<String> {
+        let content = self.spine_chapter(location.spine_idx)?;
+        self.history.replace(location);
+        Ok(content)
+    }
+
+    /// Reads spine item `item_idx` as markup, the way chapters are returned, without moving to
+    /// it. Used for the other page of a two-page spread.
+    pub fn spine_markup(&mut self, item_idx: usize) -> Result<String> {
+        let item = self.spine_manifest_item(item_idx)?;
+        let path = resolve_path(&item.href, &self.opf_path);
+        to_markup(&self.prepared_doc(&path)?)
+    }
+
+    pub fn location(&self) -> Option<&Location> {
+        self.history.current()
+    }
+
+    /// Finds the spine item a CFI points at and, within it, the innermost element with an `id`
+    /// along the CFI's path.
+    pub fn resolve_cfi(&mut self, cfi: &str) -> Result<Location> {
+        let cfi = Cfi::parse(cfi)?;
+        let itemref_step = cfi.package_path.get(1).ok_or("CFI without a spine item")?;
+        let spine_idx = (itemref_step.index / 2)
+            .checked_sub(1)
+            .filter(|idx| *idx < self.spine.len())
+            .ok_or("CFI spine item not in spine")?;
+        let doc = self.read_doc(&self.spine_item_path(spine_idx)?)?;
+        let mut elem = &doc;
+        let mut fragment = None;
+        for step in &cfi.doc_path {
+            // odd indexes point at the text between elements
+            let child = (step.index / 2)
+                .checked_sub(1)
+                .filter(|_| step.index % 2 == 0)
+                .and_then(|child_idx| elem.children().nth(child_idx));
+            elem = match child {
+                Some(c) => c,
+                None => break,
+            };
+            fragment = elem.attr("id").map(String::from).or(fragment);
+        }
+        Ok(Location {
+            spine_idx,
+            fragment,
+        })
+    }
+
+    /// Builds a CFI for `location`, pointing at the element its fragment identifies or at the
+    /// `body` of its spine item.
+    pub fn cfi(&mut self, location: &Location) -> Result<String> {
+        let spine_elem_idx = self
+            .opf_doc
+            .children()
+            .position(|c| c.name() == "spine")
+            .ok_or("spine element missing in OPF")?;
+        let itemref_id = self
+            .opf_doc
+            .children()
+            .nth(spine_elem_idx)
+            .and_then(|spine| spine.children().nth(location.spine_idx))
+            .and_then(|itemref| itemref.attr("id"))
+            .map(String::from);
+        let package_path = vec![
+            Step {
+                index: (spine_elem_idx + 1) * 2,
+                id: None,
+            },
+            Step {
+                index: (location.spine_idx + 1) * 2,
+                id: itemref_id,
+            },
+        ];
+        let doc = self.read_doc(&self.spine_item_path(location.spine_idx)?)?;
+        let doc_path = match &location.fragment {
+            Some(id) => element_path(&doc, &|e| e.attr("id") == Some(id.as_str())),
+            None => element_path(&doc, &|e| e.name() == "body"),
+        };
+        let cfi = Cfi {
+            package_path,
+            doc_path: doc_path.ok_or("location not found in its spine item")?,
+        };
+        Ok(cfi.to_string())
     }
 
     pub fn chapter_by_link(&mut self, link: &str) -> Result<String> {
-        self.current_chapter(link, &self.current_path.clone())
+        let content = self.current_chapter(link, &self.current_path.clone())?;
+        self.push_history(link)?;
+        Ok(content)
     }
 
     pub fn chapter_by_toc_link(&mut self, link: &str) -> Result<String> {
-        self.current_chapter(link, &self.toc_path.clone())
+        let content = self.current_chapter(link, &self.toc_path.clone())?;
+        self.push_history(link)?;
+        Ok(content)
     }
 
+    /// Where the current location is in the navigation history, which grows as links are
+    /// followed.
+    pub fn history_position(&self) -> usize {
+        self.history.position()
+    }
+
+    /// Moves to an earlier or later location in the navigation history. Returns `None` when
+    /// there's nothing at `position`.
+    pub fn go_to_history(&mut self, position: usize) -> Result<Option<(String, Location)>> {
+        let location = self.history.go_to(position).cloned();
+        self.open_location(location)
+    }
+
+    /// Where the reader is in the book, given how far through spine item `item_idx` they are
+    /// (from 0 to 1). The text in every spine item is measured the first time.
+    pub fn progress(&mut self, item_idx: usize, within_item: f64) -> Result<Progress> {
+        if self.text_lengths.is_none() {
+            let text_lengths = (0..self.spine.len())
+                .map(|i| self.text_length(i).unwrap_or_default())
+                .collect();
+            self.text_lengths = Some(text_lengths);
+        }
+        let text_lengths = self.text_lengths.as_ref().ok_or("no text lengths")?;
+        Ok(Progress::new(text_lengths, item_idx, within_item))
+    }
+
+    /// Extracts the element `link` points at, such as a footnote, without leaving the current
+    /// chapter. Unless `noteref` says the link is a note reference, the element has to look like
+    /// a note: an `aside` or an element typed as a footnote or endnote.
+    pub fn note(&mut self, link: &str, noteref: bool) -> Result<Option<String>> {
+        let url = utils::parse_relative_url(link)?;
+        let id = match url.fragment() {
+            Some(s) => s.to_string(),
+            None => return Ok(None),
+        };
+        let path = resolve_path(&url.path()[1..], &self.current_path);
+        let doc = self.sanitized_doc(&path)?;
+        let target = doc
+            .descendants()
+            .find(|e| e.attr("id") == Some(id.as_str()))
+            .filter(|e| noteref || is_note(e));
+        let mut note = match target {
+            Some(e) => e.clone(),
+            None => return Ok(None),
+        };
+        self.inline_resources(&mut note, &path)?;
+        Ok(Some(to_markup(&note)?))
+    }
+
+    /// Moves to the next linear spine item. Non-linear items, such as answer keys or notes, are
+    /// only reachable through links.
+    #[allow(dead_code)]
     pub fn next_chapter(&mut self) -> Result<String> {
-        self.chapter(self.current_idx()?.saturating_add(1))
+        let current_idx = self.current_idx()?;
+        self.chapter_after(current_idx)
     }
 
+    #[allow(dead_code)]
     pub fn prev_chapter(&mut self) -> Result<String> {
-        self.chapter(self.current_idx()?.saturating_sub(1))
+        let current_idx = self.current_idx()?;
+        self.chapter_before(current_idx)
+    }
+
+    /// Moves to the first linear spine item after `item_idx`, e.g. the one after a two-page
+    /// spread, or to `item_idx` itself when there's none.
+    pub fn chapter_after(&mut self, item_idx: usize) -> Result<String> {
+        let next_idx = (item_idx + 1..self.spine.len())
+            .find(|i| self.spine[*i].linear)
+            .unwrap_or(item_idx);
+        self.chapter(next_idx)
+    }
+
+    /// Moves to the last linear spine item before `item_idx`, or to `item_idx` itself when
+    /// there's none.
+    pub fn chapter_before(&mut self, item_idx: usize) -> Result<String> {
+        let prev_idx = (0..item_idx)
+            .rev()
+            .find(|i| self.spine[*i].linear)
+            .unwrap_or(item_idx);
+        self.chapter(prev_idx)
+    }
+
+    #[allow(dead_code)]
+    pub fn is_linear(&self, item_idx: usize) -> Result<bool> {
+        let spine_item = self.spine.get(item_idx).ok_or("item_idx not in spine")?;
+        Ok(spine_item.linear)
+    }
+
+    /// The direction pages progress in, which decides which side "next" is on. Unless the spine
+    /// says otherwise, it follows from the writing mode.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// The book's primary writing mode, from the `primary-writing-mode` meta or, failing that,
+    /// from the root styles of the first spine item.
+    pub fn writing_mode(&self) -> WritingMode {
+        self.writing_mode
+    }
+
+    /// Rendering properties of a spine item: the book-wide `rendition:` metadata overridden by
+    /// the item's own properties.
+    pub fn layout(&self, item_idx: usize) -> Result<LayoutProperties> {
+        let spine_item = self.spine.get(item_idx).ok_or("item_idx not in spine")?;
+        let mut layout = self.layout;
+        for property in &spine_item.properties {
+            layout.set_property(property);
+        }
+        Ok(layout)
+    }
+
+    /// The dimensions a fixed-layout spine item was designed for, from its viewport `meta`
+    /// element or, for SVG documents, its `viewBox`.
+    pub fn viewport(&mut self, item_idx: usize) -> Result<Option<(u32, u32)>> {
+        let item = self.spine_manifest_item(item_idx)?;
+        let path = resolve_path(&item.href, &self.opf_path);
+        let doc = self.read_doc(&path)?;
+        if doc.name() == "svg" {
+            let view_box: Vec<u32> = doc
+                .attr("viewBox")
+                .unwrap_or_default()
+                .split_whitespace()
+                .filter_map(|n| n.parse().ok())
+                .collect();
+            if let [_, _, width, height] = view_box.as_slice() {
+                return Ok(Some((*width, *height)));
+            }
+            return Ok(None);
+        }
+        let viewport = doc
+            .descendants()
+            .filter(|n| n.name() == "meta" && n.attr("name") == Some("viewport"))
+            .filter_map(|n| n.attr("content"))
+            .find_map(layout::parse_viewport);
+        Ok(viewport)
+    }
+
+    /// The pair of fixed-layout spine items that are shown together with `item_idx` when the
+    /// book is displayed in two-page spreads, following their `page-spread-*` properties. Pairs
+    /// are in reading order, so in right-to-left books the right page comes first.
+    pub fn spread(&self, item_idx: usize) -> Result<Option<(usize, usize)>> {
+        let (first_side, second_side) = match self.direction {
+            Direction::Ltr => (PageSpread::Left, PageSpread::Right),
+            Direction::Rtl => (PageSpread::Right, PageSpread::Left),
+        };
+        let page_spread = self.layout(item_idx)?.page_spread;
+        let (first, second) = match page_spread {
+            Some(side) if side == first_side => (item_idx, item_idx.saturating_add(1)),
+            Some(side) if side == second_side => (item_idx.saturating_sub(1), item_idx),
+            _ => return Ok(None),
+        };
+        if first == second || second >= self.spine.len() {
+            return Ok(None);
+        }
+        let first_layout = self.layout(first)?;
+        let second_layout = self.layout(second)?;
+        let paired = first_layout.layout == Layout::PrePaginated
+            && second_layout.layout == Layout::PrePaginated
+            && first_layout.page_spread == Some(first_side)
+            && second_layout.page_spread == Some(second_side);
+        Ok(if paired { Some((first, second)) } else { None })
+    }
+
+    /// The path of the NCX file, which the links in the table of contents are relative to.
+    pub fn toc_path(&self) -> &Path {
+        &self.toc_path
     }
 
     // TODO: support recursive navPoints?
@@ -151,73 +599,722 @@ impl Epub {
         Ok(toc)
     }
 
-    fn current_idx(&self) -> Result<usize> {
-        let idx = self
-            .spine
+    /// Lists the book's print pages, from the EPUB 3 navigation document's `page-list` or
+    /// otherwise from the NCX `pageList`. Books without either have no pages. The list is read
+    /// once: a page list that can't be read is reported the first time and has no pages after.
+    pub fn page_list(&mut self) -> Result<&[Page]> {
+        if self.page_list.is_none() {
+            self.page_list = Some(vec![]);
+            let mut pages = match self.nav_page_list()? {
+                Some(pages) => pages,
+                None => self.ncx_page_list()?,
+            };
+            for page in &mut pages {
+                page.spine_idx = self.spine_idx_by_path(&page.path);
+            }
+            self.page_list = Some(pages);
+        }
+        Ok(self.page_list.as_ref().map_or(&[], Vec::as_slice))
+    }
+
+    /// Finds the print page the reader is on: the last one whose start `is_passed`, given the
+    /// fragment marking it in the current chapter.
+    pub fn current_page<F>(&mut self, is_passed: F) -> Result<Option<String>>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let current_idx = self.current_idx()?;
+        let mut current_page = None;
+        for page in self.page_list()? {
+            match page.spine_idx {
+                Some(idx) if idx < current_idx => (),
+                Some(idx) if idx == current_idx => {
+                    let passed = match &page.fragment {
+                        Some(fragment) => is_passed(fragment),
+                        None => true,
+                    };
+                    if !passed {
+                        break;
+                    }
+                }
+                Some(_) => break,
+                None => continue,
+            }
+            current_page = Some(page.label.clone());
+        }
+        Ok(current_page)
+    }
+
+    /// Lists the book's landmarks, from the EPUB 3 navigation document or otherwise from the
+    /// EPUB 2 `guide`, whose reference types are mapped to their EPUB 3 equivalents.
+    pub fn landmarks(&mut self) -> Result<Vec<Landmark>> {
+        if let Some(landmarks) = self.nav_landmarks()? {
+            return Ok(landmarks);
+        }
+        let guide = match self.opf_doc.children().find(|c| c.name() == "guide") {
+            Some(guide) => guide,
+            None => return Ok(vec![]),
+        };
+        let mut landmarks = vec![];
+        for reference in guide.children().filter(|c| c.name() == "reference") {
+            let (kind, href) = match (reference.attr("type"), reference.attr("href")) {
+                (Some(kind), Some(href)) => (guide_type(kind), href),
+                _ => continue,
+            };
+            let title = reference.attr("title").unwrap_or_default();
+            landmarks.push(Landmark::new(kind, title, href, &self.opf_path)?);
+        }
+        Ok(landmarks)
+    }
+
+    /// Where the book should open: its `bodymatter` landmark, which skips covers and title
+    /// pages, or the first spine item when it has none.
+    pub fn start_location(&mut self) -> Location {
+        let landmarks = self.landmarks().unwrap_or_default();
+        landmarks
             .iter()
-            .enumerate()
-            .map(|(i, idref)| (i, self.manifest.get(idref).expect("idref not in manifest")))
-            .find(|(_, item)| resolve_path(&item.href, &self.opf_path) == self.current_path)
-            .ok_or("could not find current_path in spine")?
-            .0;
+            .filter(|l| l.kind == "bodymatter")
+            .filter_map(|l| {
+                let spine_idx = self.spine_idx_by_path(&l.path)?;
+                Some(Location {
+                    spine_idx,
+                    fragment: l.fragment.clone(),
+                })
+            })
+            .next()
+            .unwrap_or_else(|| Location::new(0, None))
+    }
+
+    pub fn chapter_by_page(&mut self, page: &Page) -> Result<String> {
+        let path_str = page.path.to_str().ok_or("invalid path")?;
+        let href = match &page.fragment {
+            Some(fragment) => format!("{}#{}", path_str, fragment),
+            None => path_str.to_string(),
+        };
+        let content = self.current_chapter(&href, Path::new(""))?;
+        self.push_history(&href)?;
+        Ok(content)
+    }
+
+    pub fn current_idx(&self) -> Result<usize> {
+        let idx = self
+            .spine_idx_by_path(&self.current_path)
+            .ok_or("could not find current_path in spine")?;
         Ok(idx)
     }
 
+    fn spine_idx_by_path(&self, path: &Path) -> Option<usize> {
+        (0..self.spine.len()).find(|i| match self.spine_manifest_item(*i) {
+            Ok(item) => resolve_path(&item.href, &self.opf_path) == path,
+            Err(_) => false,
+        })
+    }
+
+    fn nav_page_list(&mut self) -> Result<Option<Vec<Page>>> {
+        let (nav, nav_path) = match self.read_nav("page-list")? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let mut pages = vec![];
+        for anchor in nav.descendants().filter(|e| e.name() == "a") {
+            if let Some(href) = anchor.attr("href") {
+                pages.push(Page::new(&text_content(anchor), href, &nav_path)?);
+            }
+        }
+        Ok(Some(pages))
+    }
+
+    fn nav_landmarks(&mut self) -> Result<Option<Vec<Landmark>>> {
+        let (nav, nav_path) = match self.read_nav("landmarks")? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let mut landmarks = vec![];
+        for anchor in nav.descendants().filter(|e| e.name() == "a") {
+            let kind = anchor
+                .attr("epub:type")
+                .and_then(|t| t.split_whitespace().next());
+            if let (Some(kind), Some(href)) = (kind, anchor.attr("href")) {
+                let title = text_content(anchor);
+                landmarks.push(Landmark::new(kind, &title, href, &nav_path)?);
+            }
+        }
+        Ok(Some(landmarks))
+    }
+
+    /// Finds the `nav` element of the given type in the EPUB 3 navigation document, if the book
+    /// has one, along with the document's path.
+    fn read_nav(&mut self, nav_type: &str) -> Result<Option<(Element, PathBuf)>> {
+        let nav_path = match self.nav_path.clone() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let nav_doc = self.read_doc(&nav_path)?;
+        let nav = nav_doc.descendants().find(|e| {
+            let types = e.attr("epub:type").unwrap_or_default();
+            e.name() == "nav" && types.split_whitespace().any(|t| t == nav_type)
+        });
+        Ok(nav.map(|nav| (nav.clone(), nav_path)))
+    }
+
+    fn ncx_page_list(&mut self) -> Result<Vec<Page>> {
+        let path_str = self.toc_path.to_str().ok_or("invalid path")?;
+        let ncx_doc = parse_xml(self.zip.by_name(path_str)?)?;
+        let ncx_ns = ncx_doc.ns().unwrap_or_default();
+        let page_list = match ncx_doc.get_child("pageList", &ncx_ns) {
+            Some(page_list) => page_list,
+            None => return Ok(vec![]),
+        };
+        let mut pages = vec![];
+        for page_target in page_list.children().filter(|c| c.name() == "pageTarget") {
+            let label = page_target
+                .get_child("navLabel", &ncx_ns)
+                .and_then(|l| l.get_child("text", &ncx_ns))
+                .map(|t| t.text())
+                .or_else(|| page_target.attr("value").map(String::from))
+                .ok_or("no label in pageTarget")?;
+            let href = page_target
+                .get_child("content", &ncx_ns)
+                .and_then(|c| c.attr("src"))
+                .ok_or("no content in pageTarget")?;
+            pages.push(Page::new(&label, href, &self.toc_path)?);
+        }
+        Ok(pages)
+    }
+
+    fn open_location(&mut self, location: Option<Location>) -> Result<Option<(String, Location)>> {
+        match location {
+            Some(l) => Ok(Some((self.spine_chapter(l.spine_idx)?, l))),
+            None => Ok(None),
+        }
+    }
+
+    fn text_length(&mut self, item_idx: usize) -> Result<TextLength> {
+        let doc = self.read_doc(&self.spine_item_path(item_idx)?)?;
+        Ok(TextLength::of(&doc))
+    }
+
+    fn spine_item_path(&self, item_idx: usize) -> Result<PathBuf> {
+        let item = self.spine_manifest_item(item_idx)?;
+        Ok(resolve_path(&item.href, &self.opf_path))
+    }
+
+    fn spine_chapter(&mut self, item_idx: usize) -> Result<String> {
+        let item = self.spine_manifest_item(item_idx)?;
+        self.current_chapter(&item.href.clone(), &self.opf_path.clone())
+    }
+
+    /// Records the spine item a followed link led to. Links to documents outside the spine
+    /// can't be returned to, so they're left out.
+    fn push_history(&mut self, link: &str) -> Result<()> {
+        let url = utils::parse_relative_url(link)?;
+        if let Ok(idx) = self.current_idx() {
+            self.history.push(Location::new(idx, url.fragment()));
+        }
+        Ok(())
+    }
+
     fn current_chapter(&mut self, href: &str, relative_to: &Path) -> Result<String> {
         let url = utils::parse_relative_url(href)?;
         let path = &url.path()[1..]; // drop the slash
         self.current_path = resolve_path(path, relative_to);
-        let path_str = self.current_path.to_str().ok_or("invalid path")?;
-        let doc_file = self.zip.by_name(path_str)?;
-        let mut doc = parse_xml(doc_file)?;
-        self.inline_resources(&mut doc)?;
-        let mut doc_bytes = vec![];
-        doc.write_to(&mut doc_bytes)?;
-        Ok(String::from_utf8(doc_bytes)?)
+        let doc = self.prepared_doc(&self.current_path.clone())?;
+        to_markup(&doc)
+    }
+
+    /// Reads a content document sanitized and with its resources inlined, ready to be shown.
+    fn prepared_doc(&mut self, path: &Path) -> Result<Element> {
+        let mut doc = self.sanitized_doc(path)?;
+        self.inline_resources(&mut doc, path)?;
+        Ok(doc)
+    }
+
+    /// Reads a content document sanitized. The last one is kept, since following a link reads
+    /// its target twice: once to look for a note there and again to open it.
+    fn sanitized_doc(&mut self, path: &Path) -> Result<Element> {
+        if let Some((ref last_path, ref doc)) = self.last_doc {
+            if last_path == path {
+                return Ok(doc.clone());
+            }
+        }
+        let doc = self.read_doc(path)?;
+        let doc = self.sanitizer.sanitize(&doc);
+        self.last_doc = Some((path.to_path_buf(), doc.clone()));
+        Ok(doc)
+    }
+
+    fn spine_manifest_item(&self, item_idx: usize) -> Result<&ManifestItem> {
+        let spine_item = self.spine.get(item_idx).ok_or("item_idx not in spine")?;
+        let item = self
+            .manifest
+            .get(&spine_item.idref)
+            .ok_or("idref not in manifest")?;
+        self.with_fallback(item, CONTENT_DOC_TYPES)
+    }
+
+    /// Follows `item`'s fallback chain until it reaches one of `media_types`.
+    fn with_fallback<'a>(
+        &'a self,
+        item: &'a ManifestItem,
+        media_types: &[&str],
+    ) -> Result<&'a ManifestItem> {
+        let mut visited = HashSet::new();
+        let mut current = item;
+        while !media_types.contains(&current.media_type.to_lowercase().as_str()) {
+            if !visited.insert(&current.id) {
+                return Err("manifest fallback chain is circular".into());
+            }
+            let fallback_id = current
+                .fallback
+                .as_ref()
+                .ok_or("no fallback with a supported media type")?;
+            current = self
+                .manifest
+                .get(fallback_id)
+                .ok_or("fallback not in manifest")?;
+        }
+        Ok(current)
+    }
+
+    /// Reads a file from the container, undoing the obfuscation of embedded fonts.
+    pub fn resource(&mut self, path: &Path) -> Result<Vec<u8>> {
+        let path_str = path.to_str().ok_or("invalid path")?;
+        let mut bytes = vec![];
+        BufReader::new(self.zip.by_name(path_str)?).read_to_end(&mut bytes)?;
+        if let Some(algorithm) = self.encryption.get(path) {
+            encryption::deobfuscate(&mut bytes, algorithm, &self.identifiers)?;
+        }
+        Ok(bytes)
+    }
+
+    /// Parses a content document, falling back to the tag soup parser when it isn't well-formed.
+    fn read_doc(&mut self, path: &Path) -> Result<Element> {
+        let doc_bytes = self.resource(path)?;
+        parse_xml(&doc_bytes[..]).or_else(|_| parse_html(&doc_bytes[..]))
     }
 
     // TODO: make it non-recursive.
-    fn inline_resources(&mut self, elem: &mut Element) -> Result<()> {
+    fn inline_resources(&mut self, elem: &mut Element, doc_path: &Path) -> Result<()> {
         for c in elem.children_mut() {
-            self.inline_resources(c)?;
+            self.inline_resources(c, doc_path)?;
         }
 
         let attr_name = match elem.name() {
             "img" => "src",
             "image" => "xlink:href",
             "link" => "href",
+            "style" => {
+                for text in elem.texts_mut() {
+                    *text = self.inline_css_urls(text, doc_path, &mut vec![]);
+                }
+                return Ok(());
+            }
             _ => return Ok(()),
         };
         let img_href = match elem.attr(attr_name) {
             Some(s) => s,
             None => return Ok(()),
         };
-        let resource_path = resolve_path(img_href, &self.current_path);
-        let media_type = self.media_type(&resource_path).unwrap_or_default();
+        let mut resource_path = resolve_path(img_href, doc_path);
+        let mut media_type = String::new();
+        if let Some(item) = self.item_by_path(&resource_path) {
+            let item = match attr_name {
+                "href" => item,
+                _ => self.with_fallback(item, CORE_IMAGE_TYPES)?,
+            };
+            media_type = item.media_type.clone();
+            resource_path = resolve_path(&item.href, &self.opf_path);
+        }
         let mut attr_value = format!("data:{};base64,", media_type);
-        let path_str = resource_path.to_str().ok_or("invalid path")?;
-        let img_file = self.zip.by_name(path_str)?;
-        let mut bytes = vec![];
-        let mut buf_reader = BufReader::new(img_file);
-        buf_reader.read_to_end(&mut bytes)?;
+        let mut bytes = self.resource(&resource_path)?;
+        if attr_name == "href" {
+            // fonts and images referenced by the stylesheet can't be resolved from a data URL
+            let css = String::from_utf8_lossy(&bytes).into_owned();
+            let mut stylesheets = vec![resource_path.clone()];
+            bytes = self
+                .inline_css_urls(&css, &resource_path, &mut stylesheets)
+                .into_bytes();
+        }
         base64::encode_config_buf(&bytes, base64::STANDARD, &mut attr_value);
         elem.set_attr(attr_name, attr_value);
         Ok(())
     }
 
-    fn media_type(&self, path: &Path) -> Option<&str> {
-        let item_opt = self.manifest.values().find(|i| {
+    /// Replaces the relative `url()`s in a stylesheet with data URLs of what they point at,
+    /// including the stylesheets it `@import`s, which get theirs replaced too. `stylesheets` are
+    /// the ones being inlined, which aren't imported again.
+    fn inline_css_urls(
+        &mut self,
+        css: &str,
+        css_path: &Path,
+        stylesheets: &mut Vec<PathBuf>,
+    ) -> String {
+        let css = import_urls(css);
+        let mut inlined = String::with_capacity(css.len());
+        let mut rest = css.as_str();
+        while let Some(start) = rest.find("url(") {
+            let (before, after) = rest.split_at(start + "url(".len());
+            inlined.push_str(before);
+            let end = match after.find(')') {
+                Some(end) => end,
+                None => {
+                    rest = after;
+                    break;
+                }
+            };
+            let url = after[..end]
+                .trim()
+                .trim_matches(|c: char| c == '"' || c == '\'');
+            match self.css_data_url(url, css_path, stylesheets) {
+                // unquoted, since `style` elements are written out with their quotes escaped
+                Some(data_url) => inlined.push_str(&data_url),
+                None => inlined.push_str(&after[..end]),
+            }
+            rest = &after[end..];
+        }
+        inlined.push_str(rest);
+        inlined
+    }
+
+    fn css_data_url(
+        &mut self,
+        url: &str,
+        css_path: &Path,
+        stylesheets: &mut Vec<PathBuf>,
+    ) -> Option<String> {
+        if url.is_empty() || url.starts_with('#') || url.contains(':') {
+            return None;
+        }
+        let url = percent_decode(url.as_bytes()).decode_utf8_lossy();
+        let path = resolve_path(&url, css_path);
+        let media_type = self
+            .item_by_path(&path)
+            .map(|i| i.media_type.clone())
+            .unwrap_or_default();
+        let mut bytes = self.resource(&path).ok()?;
+        if media_type == "text/css" {
+            if stylesheets.contains(&path) {
+                return None;
+            }
+            stylesheets.push(path.clone());
+            let css = String::from_utf8_lossy(&bytes).into_owned();
+            bytes = self.inline_css_urls(&css, &path, stylesheets).into_bytes();
+            stylesheets.pop();
+        }
+        let mut data_url = format!("data:{};base64,", media_type);
+        base64::encode_config_buf(&bytes, base64::STANDARD, &mut data_url);
+        Some(data_url)
+    }
+
+    fn item_by_path(&self, path: &Path) -> Option<&ManifestItem> {
+        self.manifest.values().find(|i| {
             let item_path = resolve_path(&i.href, &self.opf_path);
             item_path == path
-        });
-        if let Some(item) = item_opt {
-            return Some(&item.media_type);
+        })
+    }
+}
+
+impl Document for Epub {
+    fn metadata(&self) -> Metadata {
+        let metadata_node = self.opf_doc.children().find(|n| n.name() == "metadata");
+        let fields = |name: &str| -> Vec<String> {
+            metadata_node
+                .iter()
+                .flat_map(|n| n.children())
+                .filter(|c| c.name() == name)
+                .map(|c| c.text().trim().to_string())
+                .filter(|t| !t.is_empty())
+                .collect()
+        };
+        Metadata {
+            title: fields("title").into_iter().next(),
+            authors: fields("creator"),
+            language: fields("language").into_iter().next(),
         }
-        None
+    }
+
+    fn toc(&mut self) -> Result<Toc> {
+        Epub::toc(self)
+    }
+
+    fn chapter_count(&self) -> usize {
+        self.spine.len()
+    }
+
+    fn current_idx(&self) -> Result<usize> {
+        Epub::current_idx(self)
+    }
+
+    fn chapter(&mut self, idx: usize) -> Result<String> {
+        Epub::chapter(self, idx)
+    }
+
+    fn chapter_by_link(&mut self, link: &str) -> Result<String> {
+        Epub::chapter_by_link(self, link)
+    }
+
+    fn chapter_by_toc_link(&mut self, link: &str) -> Result<String> {
+        Epub::chapter_by_toc_link(self, link)
+    }
+
+    fn chapter_after(&mut self, idx: usize) -> Result<String> {
+        Epub::chapter_after(self, idx)
+    }
+
+    fn chapter_before(&mut self, idx: usize) -> Result<String> {
+        Epub::chapter_before(self, idx)
+    }
+
+    fn resource(&mut self, path: &Path) -> Result<Vec<u8>> {
+        Epub::resource(self, path)
+    }
+
+    fn direction(&self) -> Direction {
+        Epub::direction(self)
+    }
+
+    fn writing_mode(&self) -> WritingMode {
+        Epub::writing_mode(self)
+    }
+
+    fn progress(&mut self, item_idx: usize, within_item: f64) -> Result<Progress> {
+        Epub::progress(self, item_idx, within_item)
+    }
+
+    fn location(&self) -> Option<&Location> {
+        Epub::location(self)
+    }
+
+    fn history_position(&self) -> usize {
+        Epub::history_position(self)
+    }
+
+    fn go_to_history(&mut self, position: usize) -> Result<Option<(String, Location)>> {
+        Epub::go_to_history(self, position)
+    }
+
+    fn go_to_location(&mut self, location: Location) -> Result<String> {
+        Epub::go_to_location(self, location)
+    }
+
+    fn start_location(&mut self) -> Location {
+        Epub::start_location(self)
+    }
+
+    fn note(&mut self, link: &str, noteref: bool) -> Result<Option<String>> {
+        Epub::note(self, link, noteref)
+    }
+
+    fn epub_features(&mut self) -> Option<&mut dyn EpubFeatures> {
+        Some(self)
+    }
+}
+
+impl EpubFeatures for Epub {
+    fn cfi(&mut self, location: &Location) -> Result<String> {
+        Epub::cfi(self, location)
+    }
+
+    fn resolve_cfi(&mut self, cfi: &str) -> Result<Location> {
+        Epub::resolve_cfi(self, cfi)
+    }
+
+    fn page_list(&mut self) -> Result<&[Page]> {
+        Epub::page_list(self)
+    }
+
+    fn chapter_by_page(&mut self, page: &Page) -> Result<String> {
+        Epub::chapter_by_page(self, page)
+    }
+
+    fn current_page(&mut self, is_passed: &dyn Fn(&str) -> bool) -> Result<Option<String>> {
+        Epub::current_page(self, is_passed)
+    }
+
+    fn renditions(&self) -> &[Rendition] {
+        Epub::renditions(self)
+    }
+
+    fn select_rendition(&mut self, idx: usize) -> Result<()> {
+        Epub::select_rendition(self, idx)
+    }
+
+    fn layout(&self, idx: usize) -> Result<LayoutProperties> {
+        Epub::layout(self, idx)
+    }
+
+    fn spread(&self, idx: usize) -> Result<Option<(usize, usize)>> {
+        Epub::spread(self, idx)
+    }
+
+    fn viewport(&mut self, idx: usize) -> Result<Option<(u32, u32)>> {
+        Epub::viewport(self, idx)
+    }
+
+    fn spine_markup(&mut self, idx: usize) -> Result<String> {
+        Epub::spine_markup(self, idx)
+    }
+
+    fn spine_doc(&mut self, idx: usize) -> Result<(Element, PathBuf)> {
+        Epub::spine_doc(self, idx)
+    }
+
+    fn toc_path(&self) -> &Path {
+        Epub::toc_path(self)
     }
 }
 
-fn resolve_path<'a>(path_str: &'a str, relative_to: &'a Path) -> PathBuf {
+/// The writing mode the first spine item's styles set on its root, going through its linked
+/// stylesheets and `style` elements in document order.
+fn stylesheets_writing_mode(
+    zip: &mut Zip,
+    manifest: &Manifest,
+    spine: &Spine,
+    opf_path: &Path,
+) -> Option<WritingMode> {
+    let item = manifest.get(&spine.first()?.idref)?;
+    let doc_path = resolve_path(&item.href, opf_path);
+    let doc_bytes = read_zip_file(zip, &doc_path)?;
+    let doc = parse_xml(&doc_bytes[..])
+        .or_else(|_| parse_html(&doc_bytes[..]))
+        .ok()?;
+    doc.descendants()
+        .filter_map(|elem| {
+            let rel = elem.attr("rel").unwrap_or_default().to_lowercase();
+            if elem.name() == "style" {
+                Some(text_content(elem))
+            } else if elem.name() == "link" && rel.split_whitespace().any(|r| r == "stylesheet") {
+                let path = resolve_path(elem.attr("href")?, &doc_path);
+                let css_bytes = read_zip_file(zip, &path)?;
+                Some(String::from_utf8_lossy(&css_bytes).into_owned())
+            } else {
+                None
+            }
+        })
+        .find_map(|css| layout::css_writing_mode(&css))
+}
+
+fn read_zip_file(zip: &mut Zip, path: &Path) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let file = zip.by_name(path.to_str()?).ok()?;
+    BufReader::new(file).read_to_end(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+/// Rewrites `@import "file.css"` rules as `@import url("file.css")`, so that all the URLs in a
+/// stylesheet are in `url()`s.
+fn import_urls(css: &str) -> String {
+    let mut rewritten = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("@import") {
+        let (before, after) = rest.split_at(start + "@import".len());
+        rewritten.push_str(before);
+        let url_start = after.len() - after.trim_start().len();
+        let quote = match after[url_start..].chars().next() {
+            Some(c) if c == '"' || c == '\'' => c,
+            _ => {
+                rest = after;
+                continue;
+            }
+        };
+        let url_len = match after[url_start + 1..].find(quote) {
+            Some(len) => len + 2,
+            None => {
+                rest = after;
+                break;
+            }
+        };
+        let url_end = url_start + url_len;
+        rewritten.push_str(&format!(" url({})", &after[url_start..url_end]));
+        rest = &after[url_end..];
+    }
+    rewritten.push_str(rest);
+    rewritten
+}
+
+/// Splits a link into the path of the document it points at and its fragment.
+pub fn split_href(href: &str, relative_to: &Path) -> Result<(PathBuf, Option<String>)> {
+    let url = utils::parse_relative_url(href)?;
+    // the parsed URL's path has lost any leading `..`, which `resolve_path` needs
+    let path = resolve_path(href.split('#').next().unwrap_or_default(), relative_to);
+    Ok((path, url.fragment().map(String::from)))
+}
+
+/// Maps an EPUB 2 guide reference type to the EPUB 3 landmark it corresponds to.
+fn guide_type(kind: &str) -> &str {
+    match kind {
+        "text" => "bodymatter",
+        "title-page" => "titlepage",
+        "acknowledgements" => "acknowledgments",
+        _ => kind,
+    }
+}
+
+fn split_properties(properties: Option<&str>) -> Vec<String> {
+    properties
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|p| p.to_string())
+        .collect()
+}
+
+fn read_renditions(container_doc: &Element) -> Result<Vec<Rendition>> {
+    let renditions: Vec<Rendition> = container_doc
+        .descendants()
+        .filter(|n| n.name() == "rootfile")
+        .filter_map(|n| {
+            let full_path = n.attr("full-path")?.to_string();
+            let media_type = n
+                .attr("media-type")
+                .unwrap_or("application/oebps-package+xml")
+                .to_string();
+            let attr = |name: &str| n.attr(name).map(|s| s.to_string());
+            Some(Rendition {
+                full_path,
+                media_type,
+                access_mode: attr("rendition:accessMode"),
+                label: attr("rendition:label"),
+                language: attr("rendition:language"),
+                layout: attr("rendition:layout"),
+                media: attr("rendition:media"),
+            })
+        })
+        .filter(|r| r.media_type == "application/oebps-package+xml")
+        .collect();
+    if renditions.is_empty() {
+        return Err("no rootfile in container.xml".into());
+    }
+    Ok(renditions)
+}
+
+/// Finds the CFI steps from `elem` down to the first descendant matching `target`.
+fn element_path(elem: &Element, target: &dyn Fn(&Element) -> bool) -> Option<Vec<Step>> {
+    for (child_idx, child) in elem.children().enumerate() {
+        let step = Step {
+            index: (child_idx + 1) * 2,
+            id: child.attr("id").map(String::from),
+        };
+        if target(child) {
+            return Some(vec![step]);
+        }
+        if let Some(mut path) = element_path(child, target) {
+            path.insert(0, step);
+            return Some(path);
+        }
+    }
+    None
+}
+
+fn is_note(elem: &Element) -> bool {
+    let types = elem.attr("epub:type").unwrap_or_default();
+    let role = elem.attr("role").unwrap_or_default();
+    elem.name() == "aside"
+        || types.split_whitespace().any(|t| NOTE_TYPES.contains(&t))
+        || role == "doc-footnote"
+        || role == "doc-endnote"
+}
+
+pub fn resolve_path<'a>(path_str: &'a str, relative_to: &'a Path) -> PathBuf {
     let mut built_path = PathBuf::from(relative_to);
     if path_str.is_empty() {
         return built_path;
@@ -236,12 +1333,15 @@ fn resolve_path<'a>(path_str: &'a str, relative_to: &'a Path) -> PathBuf {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::encryption::Protection;
+    use crate::format::Format;
+    use crate::test_utils::{self, OpfBuilder, XHTML};
 
     lazy_static::lazy_static! {
         static ref BYTES: Vec<u8> = std::fs::read("../static/briefe.epub").unwrap();
     }
 
-    type Result<T> = std::result::Result<T, Box<std::error::Error>>;
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
     #[test]
     fn doc_count() -> Result<()> {
@@ -322,4 +1422,610 @@ mod tests {
         assert_eq!(7, toc.len());
         Ok(())
     }
+
+    #[test]
+    fn default_direction() -> Result<()> {
+        let epub = Epub::new(BYTES.clone())?;
+        assert_eq!(Direction::Ltr, epub.direction());
+        assert_eq!(WritingMode::HorizontalTb, epub.writing_mode());
+        Ok(())
+    }
+
+    #[test]
+    fn multiple_renditions() -> Result<()> {
+        let container = r#"<?xml version="1.0"?>
+            <container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container"
+                xmlns:rendition="http://www.idpf.org/2013/rendition">
+              <rootfiles>
+                <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"
+                    rendition:label="Reflowable"/>
+                <rootfile full-path="FXL/content.opf" media-type="application/oebps-package+xml"
+                    rendition:layout="pre-paginated" rendition:language="es"/>
+              </rootfiles>
+            </container>"#;
+        let opf = OpfBuilder::new("3.0")
+            .chapter("page1", "chapter1.xhtml")
+            .build();
+        let bytes = test_utils::build_epub(&[
+            ("META-INF/container.xml", container),
+            ("OEBPS/content.opf", &opf),
+            ("OEBPS/toc.ncx", test_utils::TOC_NCX),
+            (
+                "OEBPS/chapter1.xhtml",
+                "<html><body><p>Reflowable</p></body></html>",
+            ),
+            ("FXL/content.opf", &opf),
+            ("FXL/toc.ncx", test_utils::TOC_NCX),
+            (
+                "FXL/chapter1.xhtml",
+                "<html><body><p>Fixed layout</p></body></html>",
+            ),
+        ]);
+        let mut epub = Epub::new(bytes)?;
+        assert_eq!(2, epub.renditions().len());
+        assert_eq!(Some("Reflowable".to_string()), epub.renditions()[0].label);
+        assert_eq!(Some("es".to_string()), epub.renditions()[1].language);
+        assert!(epub.chapter(0)?.contains("<p>Reflowable</p>"));
+        epub.select_rendition(1)?;
+        assert_eq!("FXL/content.opf", epub.renditions()[1].full_path);
+        assert!(epub.chapter(0)?.contains("<p>Fixed layout</p>"));
+        assert!(epub.select_rendition(2).is_err());
+        assert!(epub.chapter(0)?.contains("<p>Fixed layout</p>"));
+        Ok(())
+    }
+
+    #[test]
+    fn fixed_layout_properties() -> Result<()> {
+        let opf = OpfBuilder::new("3.0")
+            .metadata(
+                "<meta property=\"rendition:layout\">pre-paginated</meta>\
+                 <meta property=\"rendition:spread\">landscape</meta>",
+            )
+            .item("cover", "cover.xhtml", XHTML, "")
+            .item("p1", "p1.xhtml", XHTML, "")
+            .item("p2", "p2.svg", "image/svg+xml", "")
+            .item("notes", "notes.xhtml", XHTML, "")
+            .itemref("cover", "properties=\"rendition:page-spread-center\"")
+            .itemref("p1", "properties=\"page-spread-left\"")
+            .itemref("p2", "properties=\"page-spread-right\"")
+            .itemref("notes", "properties=\"rendition:layout-reflowable\"")
+            .build();
+        let page = r#"<html xmlns="http://www.w3.org/1999/xhtml"><head>
+            <meta name="viewport" content="width=1200, height=1600"/></head><body/></html>"#;
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 600 800"/>"#;
+        let bytes = test_utils::build_book(
+            &opf,
+            &[
+                ("cover.xhtml", page),
+                ("p1.xhtml", page),
+                ("p2.svg", svg),
+                ("notes.xhtml", "<html><body/></html>"),
+            ],
+        );
+        let mut epub = Epub::new(bytes)?;
+        assert_eq!(Layout::PrePaginated, epub.layout(0)?.layout);
+        assert_eq!(Some(PageSpread::Center), epub.layout(0)?.page_spread);
+        assert_eq!(Layout::Reflowable, epub.layout(3)?.layout);
+        assert_eq!(Some((1200, 1600)), epub.viewport(1)?);
+        assert_eq!(Some((600, 800)), epub.viewport(2)?);
+        assert_eq!(None, epub.viewport(3)?);
+        assert_eq!(None, epub.spread(0)?);
+        assert_eq!(Some((1, 2)), epub.spread(1)?);
+        assert_eq!(Some((1, 2)), epub.spread(2)?);
+        epub.chapter(1)?;
+        assert!(epub.spine_markup(2)?.contains("viewBox=\"0 0 600 800\""));
+        assert_eq!(1, epub.current_idx()?);
+        epub.chapter_after(2)?;
+        assert_eq!(3, epub.current_idx()?);
+        epub.chapter_before(1)?;
+        assert_eq!(0, epub.current_idx()?);
+        Ok(())
+    }
+
+    #[test]
+    fn right_to_left_spreads() -> Result<()> {
+        let opf = OpfBuilder::new("3.0")
+            .metadata("<meta property=\"rendition:layout\">pre-paginated</meta>")
+            .item("p1", "p1.xhtml", XHTML, "")
+            .item("p2", "p2.xhtml", XHTML, "")
+            .spine_attrs("page-progression-direction=\"rtl\"")
+            .itemref("p1", "properties=\"page-spread-right\"")
+            .itemref("p2", "properties=\"page-spread-left\"")
+            .build();
+        let bytes = test_utils::build_book(&opf, &[]);
+        let epub = Epub::new(bytes)?;
+        assert_eq!(Direction::Rtl, epub.direction());
+        assert_eq!(Some((0, 1)), epub.spread(1)?);
+        Ok(())
+    }
+
+    #[test]
+    fn vertical_writing_mode() -> Result<()> {
+        let opf = OpfBuilder::new("3.0")
+            .item("horizontal", "css/h.css", "text/css", "")
+            .item("vertical", "css/v.css", "text/css", "")
+            .chapter("c1", "text/c1.xhtml")
+            .build();
+        let chapter = r#"<html><head>
+            <link rel="stylesheet" href="../css/v.css"/>
+            <link rel="stylesheet" href="../css/h.css"/>
+            </head><body/></html>"#;
+        let bytes = test_utils::build_book(
+            &opf,
+            &[
+                ("text/c1.xhtml", chapter),
+                ("css/h.css", "body { writing-mode: horizontal-tb; }"),
+                ("css/v.css", "html { -webkit-writing-mode: vertical-rl; }"),
+            ],
+        );
+        let epub = Epub::new(bytes)?;
+        assert_eq!(WritingMode::VerticalRl, epub.writing_mode());
+        assert_eq!(Direction::Rtl, epub.direction());
+        Ok(())
+    }
+
+    #[test]
+    fn non_linear_items_skipped() -> Result<()> {
+        let opf = OpfBuilder::new("3.0")
+            .chapter("c1", "chapter1.xhtml")
+            .item("answers", "answers.xhtml", XHTML, "")
+            .itemref("answers", "linear=\"no\"")
+            .item("c2", "chapter2.xhtml", XHTML, "")
+            .itemref("c2", "linear=\"yes\" properties=\"page-spread-left\"")
+            .item("notes", "notes.xhtml", XHTML, "")
+            .itemref("notes", "linear=\"no\"")
+            .build();
+        let bytes = test_utils::build_book(
+            &opf,
+            &[
+                (
+                    "chapter1.xhtml",
+                    "<html><body><a href='answers.xhtml'>1</a></body></html>",
+                ),
+                ("answers.xhtml", "<html><body><p>Answers</p></body></html>"),
+                (
+                    "chapter2.xhtml",
+                    "<html><body><p>Chapter 2</p></body></html>",
+                ),
+                ("notes.xhtml", "<html><body><p>Notes</p></body></html>"),
+            ],
+        );
+        let mut epub = Epub::new(bytes)?;
+        assert!(!epub.is_linear(1)?);
+        epub.chapter(0)?;
+        assert!(epub.next_chapter()?.contains("Chapter 2"));
+        assert!(epub.next_chapter()?.contains("Chapter 2"));
+        assert_eq!(2, epub.current_idx()?);
+        assert!(epub.prev_chapter()?.contains("href=\"answers.xhtml\""));
+        assert!(epub.chapter_by_link("answers.xhtml")?.contains("Answers"));
+        assert!(epub.next_chapter()?.contains("Chapter 2"));
+        Ok(())
+    }
+
+    #[test]
+    fn manifest_fallbacks() -> Result<()> {
+        let opf = OpfBuilder::new("3.0")
+            .item(
+                "dtb",
+                "chapter1.dtb",
+                "application/x-dtbook+xml",
+                "fallback=\"c1\"",
+            )
+            .item("c1", "chapter1.xhtml", XHTML, "")
+            .item(
+                "loop",
+                "chapter2.custom",
+                "application/x-custom",
+                "fallback=\"loop2\"",
+            )
+            .item(
+                "loop2",
+                "chapter2.other",
+                "application/x-other",
+                "fallback=\"loop\"",
+            )
+            .item("jxl", "cover.jxl", "image/jxl", "fallback=\"png\"")
+            .item("png", "cover.png", "image/png", "")
+            .itemref("dtb", "")
+            .itemref("loop", "")
+            .build();
+        let bytes = test_utils::build_book(
+            &opf,
+            &[
+                ("chapter1.dtb", "<dtbook/>"),
+                (
+                    "chapter1.xhtml",
+                    "<html><body><img src='cover.jxl'/></body></html>",
+                ),
+                ("chapter2.custom", "<custom/>"),
+                ("chapter2.other", "<other/>"),
+                ("cover.jxl", "jxl"),
+                ("cover.png", "png"),
+            ],
+        );
+        let mut epub = Epub::new(bytes)?;
+        let chapter = epub.chapter(0)?;
+        assert!(chapter.contains("src=\"data:image/png;base64,cG5n\""));
+        assert_eq!(0, epub.current_idx()?);
+        assert!(epub.chapter(1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn obfuscated_fonts() -> Result<()> {
+        let opf = OpfBuilder::new("3.0")
+            .metadata(
+                "<dc:identifier>urn:isbn:9780000000000</dc:identifier>\
+                 <dc:identifier id=\"uid\">urn:uuid:12345678-1234-1234-1234-123456789abc\
+                 </dc:identifier>",
+            )
+            .chapter("c1", "chapter1.xhtml")
+            .item("css", "style.css", "text/css", "")
+            .item("font", "fonts/My Font.otf", "font/otf", "")
+            .build();
+        let encryption_xml = r#"<encryption
+              xmlns="urn:oasis:names:tc:opendocument:xmlns:container"
+              xmlns:enc="http://www.w3.org/2001/04/xmlenc#">
+              <enc:EncryptedData>
+                <enc:EncryptionMethod Algorithm="http://www.idpf.org/2008/embedding"/>
+                <enc:CipherData>
+                  <enc:CipherReference URI="OEBPS/fonts/My%20Font.otf"/>
+                </enc:CipherData>
+              </enc:EncryptedData>
+            </encryption>"#;
+        let chapter = r#"<html><head><link rel="stylesheet" href="style.css"/></head>
+            <body><p>Text</p></body></html>"#;
+        let css = r#"@font-face { font-family: F; src: url("fonts/My%20Font.otf"); }"#;
+        let font: Vec<u8> = (0..1100).map(|i| (i % 256) as u8).collect();
+        let mut obfuscated = font.clone();
+        let identifiers = vec!["urn:uuid:12345678-1234-1234-1234-123456789abc".to_string()];
+        encryption::deobfuscate(&mut obfuscated, encryption::IDPF_OBFUSCATION, &identifiers)?;
+        let bytes = test_utils::build_epub_bytes(&[
+            (
+                "META-INF/container.xml",
+                test_utils::CONTAINER_XML.as_bytes(),
+            ),
+            ("META-INF/encryption.xml", encryption_xml.as_bytes()),
+            ("OEBPS/content.opf", opf.as_bytes()),
+            ("OEBPS/toc.ncx", test_utils::TOC_NCX.as_bytes()),
+            ("OEBPS/chapter1.xhtml", chapter.as_bytes()),
+            ("OEBPS/style.css", css.as_bytes()),
+            ("OEBPS/fonts/My Font.otf", &obfuscated),
+        ]);
+        let mut epub = Epub::new(bytes)?;
+        assert_eq!(font, epub.resource(Path::new("OEBPS/fonts/My Font.otf"))?);
+        let chapter = epub.chapter(0)?;
+        let css_prefix = "href=\"data:text/css;base64,";
+        let css_start =
+            chapter.find(css_prefix).ok_or("stylesheet not inlined")? + css_prefix.len();
+        let css_len = chapter[css_start..].find('"').ok_or("unterminated href")?;
+        let inlined_css =
+            String::from_utf8(base64::decode(&chapter[css_start..css_start + css_len])?)?;
+        let font_url = format!("url(data:font/otf;base64,{})", base64::encode(&font));
+        assert!(inlined_css.contains(&font_url));
+        Ok(())
+    }
+
+    #[test]
+    fn style_urls_and_imports_inlined() -> Result<()> {
+        let opf = OpfBuilder::new("3.0")
+            .chapter("c1", "chapter1.xhtml")
+            .item("a", "css/a.css", "text/css", "")
+            .item("b", "css/b.css", "text/css", "")
+            .item("bg", "img/bg.png", "image/png", "")
+            .build();
+        let chapter = r#"<html><head><style>@import "css/a.css";
+            p { background: url(img/bg.png); }</style></head><body><p>Text</p></body></html>"#;
+        // b.css imports a.css back, which is left alone
+        let a = "@import url(b.css); h1 { background: url(../img/bg.png); }";
+        let bytes = test_utils::build_book(
+            &opf,
+            &[
+                ("chapter1.xhtml", chapter),
+                ("css/a.css", a),
+                ("css/b.css", "@import 'a.css';"),
+                ("img/bg.png", "png"),
+            ],
+        );
+        let mut epub = Epub::new(bytes)?;
+        let chapter = epub.chapter(0)?;
+        assert!(chapter.contains("p { background: url(data:image/png;base64,cG5n); }"));
+        let prefix = "@import url(data:text/css;base64,";
+        let a_start = chapter.find(prefix).ok_or("import not inlined")? + prefix.len();
+        let a_len = chapter[a_start..].find(')').ok_or("unterminated url")?;
+        let a_css = String::from_utf8(base64::decode(&chapter[a_start..a_start + a_len])?)?;
+        assert!(a_css.starts_with(prefix));
+        assert!(a_css.ends_with("h1 { background: url(data:image/png;base64,cG5n); }"));
+        Ok(())
+    }
+
+    #[test]
+    fn drm_protection() {
+        let encryption_xml = r#"<encryption
+              xmlns="urn:oasis:names:tc:opendocument:xmlns:container"
+              xmlns:enc="http://www.w3.org/2001/04/xmlenc#">
+              <enc:EncryptedData>
+                <enc:EncryptionMethod Algorithm="http://www.w3.org/2001/04/xmlenc#aes128-cbc"/>
+                <enc:CipherData>
+                  <enc:CipherReference URI="OEBPS/chapter1.xhtml"/>
+                </enc:CipherData>
+              </enc:EncryptedData>
+            </encryption>"#;
+        let bytes = test_utils::build_epub(&[
+            ("META-INF/container.xml", test_utils::CONTAINER_XML),
+            ("META-INF/encryption.xml", encryption_xml),
+            ("META-INF/rights.xml", "<adept:rights/>"),
+            ("OEBPS/toc.ncx", test_utils::TOC_NCX),
+            ("OEBPS/chapter1.xhtml", "\u{1}\u{2}\u{3}"),
+        ]);
+        match Epub::new(bytes) {
+            Err(err @ Error::DrmError(Protection::AdobeAdept)) => assert_eq!(
+                "This book is protected by Adobe DRM and cannot be opened",
+                err.to_string()
+            ),
+            _ => panic!("Adobe DRM not detected"),
+        }
+    }
+
+    #[test]
+    fn not_epub() {
+        let cbz = test_utils::build_zip(&[("01.jpg", b"jpg")]);
+        match Epub::new(cbz) {
+            Err(Error::FormatError(Format::Cbz)) => (),
+            _ => panic!("comic book archive not detected"),
+        }
+    }
+
+    #[test]
+    fn history() -> Result<()> {
+        let mut epub = Epub::new(BYTES.clone())?;
+        epub.chapter(0)?;
+        epub.next_chapter()?;
+        let toc_link = epub.toc()?[6].href.clone();
+        epub.chapter_by_toc_link(&toc_link)?;
+        let linked_idx = epub.current_idx()?;
+        assert_eq!(1, epub.history_position());
+        // following a link to where the reader already is doesn't add to the history
+        epub.chapter_by_toc_link(&toc_link)?;
+        assert_eq!(1, epub.history_position());
+        let (_, location) = epub.go_to_history(0)?.ok_or("nothing to go back to")?;
+        assert_eq!(1, location.spine_idx);
+        assert_eq!(1, epub.current_idx()?);
+        let (_, location) = epub.go_to_history(1)?.ok_or("nothing to go forward to")?;
+        assert_eq!(linked_idx, location.spine_idx);
+        assert!(epub.go_to_history(5)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn cfi() -> Result<()> {
+        let chapter = r#"<html xmlns="http://www.w3.org/1999/xhtml"><head/><body>
+            <p id="p1">One</p><div><p id="p2">Two</p></div>
+            </body></html>"#;
+        let mut epub = Epub::new(test_utils::build_single_chapter_epub(chapter))?;
+        let location = Location::new(0, Some("p2"));
+        let cfi = epub.cfi(&location)?;
+        assert_eq!("epubcfi(/6/2!/4/4/2[p2])", cfi);
+        assert_eq!(location, epub.resolve_cfi(&cfi)?);
+        assert_eq!(location, epub.resolve_cfi("epubcfi(/6/2!/4/4/2/1:2)")?);
+        assert_eq!(None, epub.resolve_cfi("epubcfi(/6/2!/4)")?.fragment);
+        assert!(epub.resolve_cfi("epubcfi(/6/4!/4)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn progress() -> Result<()> {
+        let mut epub = Epub::new(BYTES.clone())?;
+        let start = epub.progress(0, 0.0)?;
+        let middle = epub.progress(1, 0.5)?;
+        assert_eq!(3, start.item_count);
+        assert!(start.fraction < middle.fraction && middle.fraction < 1.0);
+        assert!(start.words_left > middle.words_left);
+        assert_eq!(0, epub.progress(2, 1.0)?.words_left);
+        Ok(())
+    }
+
+    #[test]
+    fn page_list() -> Result<()> {
+        let opf = OpfBuilder::new("3.0")
+            .item("nav", "nav/nav.xhtml", XHTML, "properties=\"nav\"")
+            .chapter("c1", "chapter1.xhtml")
+            .chapter("c2", "chapter2.xhtml")
+            .build();
+        let nav = r##"<html xmlns="http://www.w3.org/1999/xhtml"
+            xmlns:epub="http://www.idpf.org/2007/ops"><body>
+            <nav epub:type="toc"><ol><li><a href="../chapter1.xhtml">One</a></li></ol></nav>
+            <nav epub:type="page-list"><ol>
+              <li><a href="../chapter1.xhtml#p1">i</a></li>
+              <li><a href="../chapter1.xhtml#p2"><span>ii</span></a></li>
+              <li><a href="../chapter2.xhtml#p3">1</a></li>
+            </ol></nav>
+            </body></html>"##;
+        let chapter1 = r#"<html><body><span id="p1"/>One<span id="p2"/>Two</body></html>"#;
+        let chapter2 = r#"<html><body><span id="p3"/>Three</body></html>"#;
+        let bytes = test_utils::build_book(
+            &opf,
+            &[
+                ("nav/nav.xhtml", nav),
+                ("chapter1.xhtml", chapter1),
+                ("chapter2.xhtml", chapter2),
+            ],
+        );
+        let mut epub = Epub::new(bytes)?;
+        let pages = epub.page_list()?.to_vec();
+        let labels: Vec<_> = pages.iter().map(|p| p.label.as_str()).collect();
+        assert_eq!(vec!["i", "ii", "1"], labels);
+        assert_eq!(PathBuf::from("OEBPS/chapter1.xhtml"), pages[1].path);
+        assert_eq!(Some("p2".to_string()), pages[1].fragment);
+        assert_eq!(Some(1), pages[2].spine_idx);
+        epub.chapter(0)?;
+        assert_eq!(Some("i".to_string()), epub.current_page(|id| id == "p1")?);
+        assert!(epub.chapter_by_page(&pages[2])?.contains("Three"));
+        assert_eq!(Some("ii".to_string()), epub.current_page(|_| false)?);
+        assert_eq!(1, epub.history_position());
+        Ok(())
+    }
+
+    #[test]
+    fn ncx_page_list() -> Result<()> {
+        let mut epub = Epub::new(BYTES.clone())?;
+        let pages = epub.page_list()?;
+        assert_eq!(63, pages.len());
+        assert_eq!("7", pages[0].label);
+        assert_eq!(Some("Page_7".to_string()), pages[0].fragment);
+        epub.chapter(0)?;
+        assert_eq!(None, epub.current_page(|_| false)?);
+        assert_eq!(
+            Some("7".to_string()),
+            epub.current_page(|id| id == "Page_7")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn broken_page_list() -> Result<()> {
+        let opf = OpfBuilder::new("2.0")
+            .chapter("c1", "chapter1.xhtml")
+            .build();
+        let ncx = r#"<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+            <navMap/><pageList><pageTarget type="normal"/></pageList></ncx>"#;
+        let bytes = test_utils::build_epub(&[
+            ("META-INF/container.xml", test_utils::CONTAINER_XML),
+            ("OEBPS/content.opf", &opf),
+            ("OEBPS/toc.ncx", ncx),
+            ("OEBPS/chapter1.xhtml", "<html><body>One</body></html>"),
+        ]);
+        let mut epub = Epub::new(bytes)?;
+        assert!(epub.page_list().is_err());
+        assert!(epub.page_list()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn landmarks() -> Result<()> {
+        let opf = OpfBuilder::new("3.0")
+            .item("nav", "nav.xhtml", XHTML, "properties=\"nav\"")
+            .chapter("cover", "cover.xhtml")
+            .chapter("c1", "chapter1.xhtml")
+            .reference("text", "Start", "cover.xhtml")
+            .build();
+        let nav = r##"<html xmlns="http://www.w3.org/1999/xhtml"
+            xmlns:epub="http://www.idpf.org/2007/ops"><body>
+            <nav epub:type="landmarks"><ol>
+              <li><a epub:type="cover" href="cover.xhtml">Cover</a></li>
+              <li><a epub:type="bodymatter" href="chapter1.xhtml#start">Start</a></li>
+            </ol></nav>
+            </body></html>"##;
+        let bytes = test_utils::build_book(
+            &opf,
+            &[
+                ("nav.xhtml", nav),
+                ("cover.xhtml", "<html><body><p>Cover</p></body></html>"),
+                (
+                    "chapter1.xhtml",
+                    "<html><body><p id='start'>One</p></body></html>",
+                ),
+            ],
+        );
+        let mut epub = Epub::new(bytes)?;
+        let landmarks = epub.landmarks()?;
+        let kinds: Vec<_> = landmarks.iter().map(|l| l.kind.as_str()).collect();
+        assert_eq!(vec!["cover", "bodymatter"], kinds);
+        assert_eq!(Location::new(1, Some("start")), epub.start_location());
+        Ok(())
+    }
+
+    #[test]
+    fn guide_landmarks() -> Result<()> {
+        let mut epub = Epub::new(BYTES.clone())?;
+        assert!(epub.landmarks()?.is_empty());
+        assert_eq!(Location::new(0, None), epub.start_location());
+        let opf = OpfBuilder::new("2.0")
+            .chapter("title", "title.xhtml")
+            .chapter("c1", "text/chapter1.xhtml")
+            .reference("title-page", "Title", "title.xhtml")
+            .reference("text", "Text", "text/chapter1.xhtml")
+            .build();
+        let bytes = test_utils::build_book(
+            &opf,
+            &[
+                ("title.xhtml", "<html><body><p>Title</p></body></html>"),
+                (
+                    "text/chapter1.xhtml",
+                    "<html><body><p>One</p></body></html>",
+                ),
+            ],
+        );
+        let mut epub = Epub::new(bytes)?;
+        let landmarks = epub.landmarks()?;
+        assert_eq!("titlepage", landmarks[0].kind);
+        assert_eq!(
+            PathBuf::from("OEBPS/text/chapter1.xhtml"),
+            landmarks[1].path
+        );
+        assert_eq!(Location::new(1, None), epub.start_location());
+        Ok(())
+    }
+
+    #[test]
+    fn scripts_removed_from_chapters() -> Result<()> {
+        let chapter = r#"<html xmlns="http://www.w3.org/1999/xhtml"><head>
+            <script>alert("head")</script></head>
+            <body onload="alert('body')"><p>Text<script src="evil.js"/></p>
+            <a href="javascript:alert('link')">link</a>
+            <iframe src="https://example.com"></iframe>
+            <svg xmlns="http://www.w3.org/2000/svg"><script>alert("svg")</script></svg>
+            </body></html>"#;
+        let mut epub = Epub::new(test_utils::build_single_chapter_epub(chapter))?;
+        let chapter_html = epub.chapter(0)?;
+        assert!(!chapter_html.contains("script"));
+        assert!(!chapter_html.contains("alert"));
+        assert!(!chapter_html.contains("iframe"));
+        assert!(chapter_html.contains("<p>Text</p>"));
+        assert!(chapter_html.contains("<a>link</a>"));
+        Ok(())
+    }
+
+    #[test]
+    fn notes() -> Result<()> {
+        let chapter = r##"<html xmlns="http://www.w3.org/1999/xhtml"
+            xmlns:epub="http://www.idpf.org/2007/ops"><body>
+            <p id="text">Text<a epub:type="noteref" href="#n1">1</a></p>
+            <aside epub:type="footnote" id="n1"><p>Note<script>alert(1)</script></p></aside>
+            </body></html>"##;
+        let mut epub = Epub::new(test_utils::build_single_chapter_epub(chapter))?;
+        epub.chapter(0)?;
+        let note = epub.note("#n1", false)?.ok_or("no note")?;
+        assert!(note.contains("<p>Note</p>"));
+        assert!(note.contains("id=\"n1\""));
+        assert!(!note.contains("Text"));
+        assert_eq!(None, epub.note("#text", false)?);
+        assert!(epub.note("chapter1.xhtml#text", true)?.is_some());
+        assert_eq!(None, epub.note("#missing", true)?);
+        assert_eq!(0, epub.current_idx()?);
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_chapters_sanitized() -> Result<()> {
+        let chapter = "<html><body><p>Tom & Jerry<br><span onmouseover='alert(1)'>!</body></html>";
+        let mut epub = Epub::new(test_utils::build_single_chapter_epub(chapter))?;
+        let chapter_html = epub.chapter(0)?;
+        assert!(chapter_html.contains("Tom &amp; Jerry<br/>"));
+        assert!(!chapter_html.contains("onmouseover"));
+        Ok(())
+    }
+
+    #[test]
+    fn custom_sanitizer() -> Result<()> {
+        let chapter = "<html><body><iframe src='https://example.org'/><em>e</em></body></html>";
+        let mut epub = Epub::new(test_utils::build_single_chapter_epub(chapter))?;
+        let mut sanitizer = Sanitizer::default();
+        sanitizer.allow_element("iframe").deny_element("em");
+        epub.set_sanitizer(sanitizer);
+        let chapter_html = epub.chapter(0)?;
+        assert!(chapter_html.contains("<iframe src=\"https://example.org\"/>"));
+        assert!(!chapter_html.contains("<em>"));
+        Ok(())
+    }
 }
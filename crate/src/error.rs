@@ -1,3 +1,5 @@
+use crate::encryption::Protection;
+use crate::format::Format;
 use std::convert::From;
 use std::fmt;
 use std::io;
@@ -11,10 +13,15 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[derive(Debug)]
 pub enum Error {
     LeedorError(&'static str),
+    DrmError(Protection),
+    FormatError(Format),
+    /// A book in another format that couldn't be converted to EPUB.
+    ConversionError(Format, Box<Error>),
     IoError(io::Error),
     MinidomError(minidom::Error),
     StringError(FromUtf8Error),
     UrlError(ParseError),
+    XmlError(quick_xml::Error),
     ZipError(ZipError),
 }
 
@@ -48,6 +55,12 @@ impl From<ParseError> for Error {
     }
 }
 
+impl From<quick_xml::Error> for Error {
+    fn from(err: quick_xml::Error) -> Error {
+        Error::XmlError(err)
+    }
+}
+
 impl From<ZipError> for Error {
     fn from(err: ZipError) -> Error {
         Error::ZipError(err)
@@ -65,10 +78,14 @@ impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         let error_str = match self {
             Error::LeedorError(s) => format!("LeedorError: {}", s),
+            Error::DrmError(p) => format!("This book is protected by {} and cannot be opened", p),
+            Error::FormatError(f) => format!("This is {}, not an EPUB book", f),
+            Error::ConversionError(f, e) => format!("This is {} that couldn't be read: {}", f, e),
             Error::IoError(e) => format!("IoError: {}", e),
             Error::MinidomError(e) => format!("MinidomError: {}", e),
             Error::StringError(e) => format!("StringError: {}", e),
             Error::UrlError(e) => format!("UrlError: {}", e),
+            Error::XmlError(e) => format!("XmlError: {}", e),
             Error::ZipError(e) => format!("ZipError: {}", e),
         };
         write!(fmt, "{}", error_str)
@@ -0,0 +1,368 @@
+//! Exports a range of a book's chapters as a single self-contained HTML5 file, for sharing them
+//! with people who don't have a reader. Chapters go one after another in `section`s, after a
+//! table of contents, with their images and stylesheets embedded and their links to each other
+//! pointing within the file.
+
+use crate::builder::escape;
+use crate::document::{self, Document};
+use crate::epub::{split_href, TocItem};
+use crate::error::Result;
+use crate::html::VOID_ELEMENTS;
+use crate::xml::{text_content, Descend};
+use minidom::Element;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use url::percent_encoding::percent_decode;
+use url::Url;
+
+const CONTENTS_ID: &str = "contents";
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// A spine item on its way into the exported file.
+struct Part {
+    path: PathBuf,
+    body: Element,
+}
+
+/// Where the ids of the exported documents end up. Ids are unique within a document but not
+/// across a book, so the ones that clash get a numeric suffix.
+#[derive(Default)]
+struct Anchors {
+    used: HashSet<String>,
+    /// The id of the `section` each document goes in, by the document's path.
+    sections: HashMap<PathBuf, String>,
+    /// The new ids of the elements in each document, by the document's path and their old id.
+    ids: HashMap<(PathBuf, String), String>,
+}
+
+impl Anchors {
+    /// Takes `id`, or `id` with the lowest suffix that makes it unique.
+    fn reserve(&mut self, id: &str) -> String {
+        let mut unique_id = id.to_string();
+        let mut suffix = 1;
+        while self.used.contains(&unique_id) {
+            suffix += 1;
+            unique_id = format!("{}-{}", id, suffix);
+        }
+        self.used.insert(unique_id.clone());
+        unique_id
+    }
+
+    fn add_part(&mut self, part: &Part, section_id: &str) {
+        let section_id = self.reserve(section_id);
+        // the body's id now belongs to the section
+        if let Some(id) = part.body.attr("id") {
+            let key = (part.path.clone(), id.to_string());
+            self.ids.insert(key, section_id.clone());
+        }
+        for elem in part.body.descendants() {
+            let id = match elem.attr("id") {
+                Some(id) => id,
+                None => continue,
+            };
+            let key = (part.path.clone(), id.to_string());
+            if !self.ids.contains_key(&key) {
+                let new_id = self.reserve(id);
+                self.ids.insert(key, new_id);
+            }
+        }
+        self.sections.insert(part.path.clone(), section_id);
+    }
+
+    fn id(&self, path: &Path, id: &str) -> Option<&String> {
+        self.ids.get(&(path.to_path_buf(), id.to_string()))
+    }
+
+    /// The in-page link `href`, relative to `relative_to`, becomes. Links to documents that
+    /// aren't in the file have nowhere to go.
+    fn link(&self, href: &str, relative_to: &Path) -> Option<String> {
+        let (path, fragment) = split_href(href, relative_to).ok()?;
+        let section_id = self.sections.get(&path)?;
+        let id = fragment
+            .map(|f| {
+                percent_decode(f.as_bytes())
+                    .decode_utf8_lossy()
+                    .into_owned()
+            })
+            .and_then(|f| self.id(&path, &f))
+            .unwrap_or(section_id);
+        Some(format!("#{}", id))
+    }
+}
+
+/// Opens a book and exports spine items `range`, or all of them when there's no range. Only
+/// books that open as EPUBs, which FictionBooks do, can be exported.
+pub fn export_html(bytes: Vec<u8>, range: Option<Range<usize>>) -> Result<String> {
+    let mut doc = document::open(bytes)?;
+    export_document(doc.as_mut(), range)
+}
+
+/// Exports spine items `range` of `doc`, or all of them when there's no range, without moving
+/// the reader to any of them.
+pub fn export_document(doc: &mut dyn Document, range: Option<Range<usize>>) -> Result<String> {
+    let range = match range {
+        Some(range) => range,
+        None => 0..doc.chapter_count(),
+    };
+    if range.start >= range.end || range.end > doc.chapter_count() {
+        return Err("chapter range out of bounds".into());
+    }
+    let metadata = doc.metadata();
+    let toc = doc.toc().unwrap_or_default();
+    let epub = doc
+        .epub_features()
+        .ok_or("only EPUB and FictionBook books can be exported")?;
+    let mut anchors = Anchors::default();
+    anchors.reserve(CONTENTS_ID);
+    let mut parts = vec![];
+    let mut styles = vec![];
+    for idx in range {
+        let (doc, path) = epub.spine_doc(idx)?;
+        for style in doc_styles(&doc) {
+            if !styles.contains(&style) {
+                styles.push(style);
+            }
+        }
+        // SVG documents have no body, so they go in whole
+        let body = match doc.children().find(|c| c.name() == "body") {
+            Some(body) => body.clone(),
+            None => Element::builder("body").append(doc.clone()).build(),
+        };
+        let part = Part { path, body };
+        anchors.add_part(&part, &format!("chapter-{}", idx + 1));
+        parts.push(part);
+    }
+    let title = metadata.title.unwrap_or_else(|| "Untitled".to_string());
+    let mut html = String::from("<!DOCTYPE html>\n<html");
+    if let Some(language) = metadata.language {
+        html.push_str(&format!(" lang=\"{}\"", escape(&language)));
+    }
+    html.push_str("><head><meta charset=\"utf-8\">");
+    html.push_str(&format!("<title>{}</title>", escape(&title)));
+    for style in styles {
+        html.push_str(&format!("<style>{}</style>", escape_style(&style)));
+    }
+    html.push_str("</head><body>");
+    html.push_str(&contents(&toc, epub.toc_path(), &parts, &anchors, &title));
+    for part in &parts {
+        write_section(&mut html, part, &anchors);
+    }
+    html.push_str("</body></html>\n");
+    Ok(html)
+}
+
+/// The stylesheets a document links to, which come inlined as data URLs, and the ones in its
+/// `style` elements.
+fn doc_styles(doc: &Element) -> Vec<String> {
+    let head = match doc.children().find(|c| c.name() == "head") {
+        Some(head) => head,
+        None => return vec![],
+    };
+    let mut styles = vec![];
+    for elem in head.descendants() {
+        let rel = elem.attr("rel").unwrap_or_default().to_lowercase();
+        if elem.name() == "style" {
+            styles.push(text_content(elem));
+        } else if elem.name() == "link" && rel.split_whitespace().any(|r| r == "stylesheet") {
+            let href = elem.attr("href").unwrap_or_default();
+            let data = href.split("base64,").nth(1).unwrap_or_default();
+            if let Ok(css) = base64::decode(data) {
+                styles.push(String::from_utf8_lossy(&css).into_owned());
+            }
+        }
+    }
+    styles
+}
+
+/// Builds the table of contents from the book's, leaving out what isn't in the file. Books
+/// whose table of contents doesn't cover the exported chapters get one entry per chapter.
+fn contents(
+    toc: &[TocItem],
+    toc_path: &Path,
+    parts: &[Part],
+    anchors: &Anchors,
+    title: &str,
+) -> String {
+    let mut entries = vec![];
+    for item in toc {
+        if let Some(link) = anchors.link(&item.href, toc_path) {
+            entries.push((link, item.text.clone()));
+        }
+    }
+    if entries.is_empty() {
+        for (idx, part) in parts.iter().enumerate() {
+            let heading = part
+                .body
+                .descendants()
+                .find(|e| HEADINGS.contains(&e.name()))
+                .map(|h| text_content(h).trim().to_string())
+                .filter(|h| !h.is_empty())
+                .unwrap_or_else(|| format!("Chapter {}", idx + 1));
+            entries.push((format!("#{}", anchors.sections[&part.path]), heading));
+        }
+    }
+    let mut html = format!("<nav id=\"{}\"><h1>{}</h1><ol>", CONTENTS_ID, escape(title));
+    for (link, text) in entries {
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>",
+            escape(&link),
+            escape(&text)
+        ));
+    }
+    html.push_str("</ol></nav>");
+    html
+}
+
+/// Writes a document's body as a `section`, which keeps the body's attributes.
+fn write_section(html: &mut String, part: &Part, anchors: &Anchors) {
+    let section_id = &anchors.sections[&part.path];
+    html.push_str(&format!("<section id=\"{}\"", escape(section_id)));
+    for (key, value) in part.body.attrs().filter(|(key, _)| *key != "id") {
+        html.push_str(&format!(" {}=\"{}\"", key, escape(value)));
+    }
+    html.push('>');
+    write_children(html, &part.body, &part.path, anchors);
+    html.push_str("</section>");
+}
+
+/// Writes `elem` as HTML5, giving it its new id and pointing its links within the file.
+fn write_element(html: &mut String, elem: &Element, path: &Path, anchors: &Anchors) {
+    html.push_str(&format!("<{}", elem.name()));
+    for (key, value) in elem.attrs() {
+        let value = match (elem.name(), key) {
+            (_, "id") => anchors.id(path, value).cloned(),
+            ("a", "href") if Url::parse(value).is_err() => anchors.link(value, path),
+            _ => Some(value.to_string()),
+        };
+        if let Some(value) = value {
+            html.push_str(&format!(" {}=\"{}\"", key, escape(&value)));
+        }
+    }
+    html.push('>');
+    if VOID_ELEMENTS.contains(&elem.name()) {
+        return;
+    }
+    write_children(html, elem, path, anchors);
+    html.push_str(&format!("</{}>", elem.name()));
+}
+
+fn write_children(html: &mut String, elem: &Element, path: &Path, anchors: &Anchors) {
+    for node in elem.nodes() {
+        if let Some(text) = node.as_text() {
+            // HTML parsers don't unescape the text in `style` elements
+            if elem.name() == "style" {
+                html.push_str(&escape_style(text));
+            } else {
+                html.push_str(&escape(text));
+            }
+        } else if let Some(child) = node.as_element() {
+            write_element(html, child, path, anchors);
+        }
+    }
+}
+
+/// Keeps CSS from ending the `style` element it's written in, by writing the slash of any
+/// `</style` in it as `\/`, which is still a slash to CSS.
+fn escape_style(css: &str) -> String {
+    let mut escaped = String::with_capacity(css.len());
+    let mut last = 0;
+    for (idx, _) in css.to_ascii_lowercase().match_indices("</style") {
+        escaped.push_str(&css[last..=idx]);
+        escaped.push('\\');
+        last = idx + 1;
+    }
+    escaped.push_str(&css[last..]);
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{self, OpfBuilder};
+
+    const CHAPTER_1: &str = r#"<html xmlns="http://www.w3.org/1999/xhtml">
+<head><link rel="stylesheet" href="style.css"/></head>
+<body id="top"><p id="note">One &amp; <a href="chapter2.xhtml#note">two</a><br/></p>
+<img src="cat.png" alt="A cat"/></body></html>"#;
+    const CHAPTER_2: &str = r#"<html xmlns="http://www.w3.org/1999/xhtml">
+<head><link rel="stylesheet" href="style.css"/><style>p > a { color: red; }</style></head>
+<body><p id="note"><a href="chapter1.xhtml">Back</a>, <a href="chapter3.xhtml">on</a>
+<a href="https://example.com">out</a></p></body></html>"#;
+
+    fn build_book() -> Vec<u8> {
+        let opf = OpfBuilder::new("2.0")
+            .metadata(
+                "<dc:identifier id=\"uid\">urn:test:export</dc:identifier>\
+                 <dc:title>Tom &amp; Jerry</dc:title><dc:language>en</dc:language>",
+            )
+            .chapter("chapter1", "chapter1.xhtml")
+            .chapter("chapter2", "chapter2.xhtml")
+            .chapter("chapter3", "chapter3.xhtml")
+            .item("css", "style.css", "text/css", "")
+            .item("cat", "cat.png", "image/png", "")
+            .build();
+        let ncx = r#"<?xml version="1.0"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1"><navMap>
+  <navPoint id="np-1"><navLabel><text>One</text></navLabel>
+    <content src="chapter1.xhtml"/></navPoint>
+  <navPoint id="np-2"><navLabel><text>Two</text></navLabel>
+    <content src="chapter2.xhtml#note"/></navPoint>
+  <navPoint id="np-3"><navLabel><text>Three</text></navLabel>
+    <content src="chapter3.xhtml"/></navPoint>
+</navMap></ncx>"#;
+        test_utils::build_epub(&[
+            ("META-INF/container.xml", test_utils::CONTAINER_XML),
+            ("OEBPS/content.opf", &opf),
+            ("OEBPS/toc.ncx", ncx),
+            ("OEBPS/chapter1.xhtml", CHAPTER_1),
+            ("OEBPS/chapter2.xhtml", CHAPTER_2),
+            (
+                "OEBPS/chapter3.xhtml",
+                "<html><body><p>Three</p></body></html>",
+            ),
+            ("OEBPS/style.css", "p { margin: 0; }"),
+            ("OEBPS/cat.png", "png"),
+        ])
+    }
+
+    #[test]
+    fn single_file() -> Result<()> {
+        let html = export_html(build_book(), Some(0..2))?;
+        assert!(
+            html.starts_with("<!DOCTYPE html>\n<html lang=\"en\"><head><meta charset=\"utf-8\">")
+        );
+        assert!(html.contains("<title>Tom &amp; Jerry</title>"));
+        // the stylesheet both chapters link to is only embedded once
+        assert_eq!(1, html.matches("<style>p { margin: 0; }</style>").count());
+        assert!(html.contains("<style>p > a { color: red; }</style>"));
+        assert!(html.contains(
+            "<nav id=\"contents\"><h1>Tom &amp; Jerry</h1><ol>\
+             <li><a href=\"#chapter-1\">One</a></li><li><a href=\"#note-2\">Two</a></li></ol></nav>"
+        ));
+        assert!(html.contains("<section id=\"chapter-1\"><p id=\"note\">One &amp; "));
+        assert!(html.contains("<a href=\"#note-2\">two</a><br></p>"));
+        assert!(html.contains("<img alt=\"A cat\" src=\"data:image/png;base64,cG5n\">"));
+        assert!(html.contains("<section id=\"chapter-2\"><p id=\"note-2\">"));
+        assert!(html.contains("<a href=\"#chapter-1\">Back</a>, <a>on</a>"));
+        assert!(html.contains("<a href=\"https://example.com\">out</a>"));
+        assert!(!html.contains("Three"));
+        Ok(())
+    }
+
+    #[test]
+    fn whole_book() -> Result<()> {
+        let html = export_html(build_book(), None)?;
+        assert!(html.contains("<li><a href=\"#chapter-3\">Three</a></li>"));
+        assert!(html.contains("<a href=\"#chapter-3\">on</a>"));
+        assert!(export_html(build_book(), Some(2..4)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn style_end_tags() {
+        let css = "p::after { content: \"</style>\" } q::after { content: \"</STYLE\" }";
+        let expected = "p::after { content: \"<\\/style>\" } q::after { content: \"<\\/STYLE\" }";
+        assert_eq!(expected, escape_style(css));
+    }
+}
@@ -0,0 +1,417 @@
+//! FictionBook 2 (FB2) books: a single XML file, often zipped as `.fb2.zip`, with the text in
+//! `body` elements and the images embedded in it as base64 `binary` elements. They're read by
+//! converting them to EPUB, one chapter per top-level section.
+
+use crate::builder::{chapter_href, EpubBuilder};
+use crate::error::Result;
+use crate::xml::{parse_xml, text_content, Descend};
+use minidom::{Element, ElementBuilder};
+use std::collections::HashMap;
+use std::io::Cursor;
+use zip::ZipArchive;
+
+/// Attributes carried over to the XHTML elements FB2 elements are converted to.
+const KEPT_ATTRIBUTES: &[&str] = &["id", "align", "colspan", "rowspan"];
+
+/// The contents of a chapter of the converted book.
+enum Part<'a> {
+    /// What comes before the first section of the main body, such as the cover and epigraphs.
+    Lead(Vec<&'a Element>),
+    Section(&'a Element),
+    /// A body of notes, which link to them by id.
+    Notes(&'a Element),
+}
+
+impl<'a> Part<'a> {
+    fn elements(&self) -> Vec<&'a Element> {
+        match self {
+            Part::Lead(elements) => elements.clone(),
+            Part::Section(elem) | Part::Notes(elem) => vec![*elem],
+        }
+    }
+}
+
+/// Converts FB2 elements to XHTML, pointing links at the chapters their targets end up in.
+struct Converter {
+    chapters_by_id: HashMap<String, usize>,
+}
+
+impl Converter {
+    fn new(parts: &[Part]) -> Converter {
+        let mut chapters_by_id = HashMap::new();
+        for (idx, part) in parts.iter().enumerate() {
+            for elem in part.elements() {
+                let ids = std::iter::once(elem)
+                    .chain(elem.descendants())
+                    .filter_map(|e| e.attr("id"));
+                for id in ids {
+                    chapters_by_id.insert(id.to_string(), idx);
+                }
+            }
+        }
+        Converter { chapters_by_id }
+    }
+
+    fn body(&self, part: &Part) -> Element {
+        let mut builder = Element::builder("body");
+        match part {
+            Part::Lead(elements) => {
+                for elem in elements {
+                    builder = self.append(elem, 0, builder);
+                }
+            }
+            Part::Section(section) => builder = builder.append(self.section(section, 1)),
+            Part::Notes(body) => {
+                for child in body.children() {
+                    builder = match child.name() {
+                        "section" => builder.append(self.note(child)),
+                        _ => self.append(child, 0, builder),
+                    };
+                }
+            }
+        }
+        builder.build()
+    }
+
+    /// Appends the conversion of `elem` to `builder`, or that of its children if it has no
+    /// XHTML counterpart. `level` is that of the innermost section `elem` is in.
+    fn append(&self, elem: &Element, level: usize, builder: ElementBuilder) -> ElementBuilder {
+        match self.convert(elem, level) {
+            Some(converted) => builder.append(converted),
+            None => self.append_children(elem, level, builder),
+        }
+    }
+
+    fn append_children(
+        &self,
+        elem: &Element,
+        level: usize,
+        mut builder: ElementBuilder,
+    ) -> ElementBuilder {
+        for node in elem.nodes() {
+            if let Some(text) = node.as_text() {
+                builder = builder.append(text.to_string());
+            } else if let Some(child) = node.as_element() {
+                builder = self.append(child, level, builder);
+            }
+        }
+        builder
+    }
+
+    fn convert(&self, elem: &Element, level: usize) -> Option<Element> {
+        let (name, class) = match elem.name() {
+            "section" => return Some(self.section(elem, level + 1)),
+            "title" => return Some(self.title(elem, level)),
+            "image" => return Some(image(elem)),
+            "a" => return Some(self.link(elem, level)),
+            "empty-line" => return Some(Element::builder("br").build()),
+            "p" | "strong" | "sub" | "sup" | "code" | "table" | "tr" | "th" | "td" => {
+                (elem.name(), None)
+            }
+            "emphasis" => ("em", None),
+            "strikethrough" => ("s", None),
+            "style" => ("span", None),
+            "subtitle" => ("p", Some("subtitle")),
+            "epigraph" => ("blockquote", Some("epigraph")),
+            "cite" => ("blockquote", Some("cite")),
+            "annotation" => ("div", Some("annotation")),
+            "poem" => ("div", Some("poem")),
+            "stanza" => ("div", Some("stanza")),
+            "v" => ("p", Some("verse")),
+            "text-author" => ("p", Some("text-author")),
+            "date" => ("p", Some("date")),
+            _ => return None,
+        };
+        let builder = start(name, elem, class);
+        Some(self.append_children(elem, level, builder).build())
+    }
+
+    fn section(&self, section: &Element, level: usize) -> Element {
+        let builder = start("section", section, None);
+        self.append_children(section, level, builder).build()
+    }
+
+    /// Converts a title to a heading of the section's level, with a line for each paragraph.
+    fn title(&self, title: &Element, level: usize) -> Element {
+        let mut builder = start(&format!("h{}", level.max(1).min(6)), title, None);
+        let paragraphs = title.children().filter(|c| c.name() == "p");
+        for (idx, paragraph) in paragraphs.enumerate() {
+            if idx > 0 {
+                builder = builder.append(Element::builder("br").build());
+            }
+            builder = self.append_children(paragraph, level, builder);
+        }
+        builder.build()
+    }
+
+    fn link(&self, link: &Element, level: usize) -> Element {
+        let mut builder = start("a", link, None);
+        if let Some(href) = href(link) {
+            builder = builder.attr("href", self.resolve(href));
+        }
+        if link.attr("type") == Some("note") {
+            builder = builder.attr("epub:type", "noteref");
+        }
+        self.append_children(link, level, builder).build()
+    }
+
+    /// Turns a note into an `aside`, leaving out its title, which is usually just its number.
+    fn note(&self, section: &Element) -> Element {
+        let mut builder = start("aside", section, None).attr("epub:type", "footnote");
+        for child in section.children().filter(|c| c.name() != "title") {
+            builder = self.append(child, 1, builder);
+        }
+        builder.build()
+    }
+
+    fn resolve(&self, href: &str) -> String {
+        let mut parts = href.splitn(2, '#');
+        if let (Some(""), Some(id)) = (parts.next(), parts.next()) {
+            if let Some(idx) = self.chapters_by_id.get(id) {
+                return format!("{}{}", chapter_href(*idx), href);
+            }
+        }
+        href.to_string()
+    }
+}
+
+/// Converts an FB2 book, or a zip with one in it, to an EPUB.
+pub fn to_epub(bytes: &[u8]) -> Result<Vec<u8>> {
+    let doc = read_fb2(bytes)?;
+    let description = child(&doc, "description");
+    let title_info = description.and_then(|d| child(d, "title-info"));
+    let document_info = description.and_then(|d| child(d, "document-info"));
+    let title = field(title_info, "book-title").unwrap_or_else(|| "Untitled".to_string());
+    let language = field(title_info, "lang").unwrap_or_else(|| "und".to_string());
+    let identifier = field(document_info, "id").unwrap_or_else(|| title.clone());
+    let mut builder = EpubBuilder::new(&identifier, &title, &language);
+    let authors = title_info
+        .into_iter()
+        .flat_map(|t| t.children())
+        .filter(|c| c.name() == "author");
+    for author in authors {
+        if let Some(name) = author_name(author) {
+            builder.author(&name);
+        }
+    }
+
+    let bodies: Vec<&Element> = doc.children().filter(|c| c.name() == "body").collect();
+    let main_idx = bodies
+        .iter()
+        .position(|b| b.attr("name").is_none())
+        .unwrap_or(0);
+    let main_body = bodies.get(main_idx).ok_or("no body in FB2 book")?;
+    let mut parts = vec![];
+    let mut titles = vec![];
+    let mut lead: Vec<&Element> = title_info
+        .and_then(|t| child(t, "coverpage"))
+        .into_iter()
+        .collect();
+    lead.extend(main_body.children().filter(|c| c.name() != "section"));
+    if !lead.is_empty() {
+        parts.push(Part::Lead(lead));
+        titles.push(title.clone());
+    }
+    let sections = main_body.children().filter(|c| c.name() == "section");
+    for (idx, section) in sections.enumerate() {
+        parts.push(Part::Section(section));
+        titles.push(title_text(section).unwrap_or_else(|| format!("Section {}", idx + 1)));
+    }
+    let other_bodies = bodies
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| *idx != main_idx);
+    for (_, body) in other_bodies {
+        parts.push(Part::Notes(body));
+        titles.push(title_text(body).unwrap_or_else(|| "Notes".to_string()));
+    }
+
+    let converter = Converter::new(&parts);
+    for (part, title) in parts.iter().zip(&titles) {
+        builder.chapter(title, converter.body(part));
+    }
+    for binary in doc.children().filter(|c| c.name() == "binary") {
+        let id = match binary.attr("id") {
+            Some(id) => id,
+            None => continue,
+        };
+        let media_type = binary.attr("content-type").unwrap_or_default();
+        let data: String = binary.text().split_whitespace().collect();
+        let bytes = base64::decode(&data).map_err(|_| "invalid base64 in FB2 binary")?;
+        builder.resource(&image_href(id), media_type, bytes);
+    }
+    builder.build()
+}
+
+/// Parses the FB2 file in `bytes`, unzipping it first if need be.
+fn read_fb2(bytes: &[u8]) -> Result<Element> {
+    let mut zip = match ZipArchive::new(Cursor::new(bytes)) {
+        Ok(zip) => zip,
+        Err(_) => return parse_xml(bytes),
+    };
+    for i in 0..zip.len() {
+        let file = zip.by_index(i)?;
+        if file.name().to_lowercase().ends_with(".fb2") {
+            return parse_xml(file);
+        }
+    }
+    Err("no FB2 file in zip archive".into())
+}
+
+fn start(name: &str, elem: &Element, class: Option<&str>) -> ElementBuilder {
+    let mut builder = Element::builder(name);
+    for attr in KEPT_ATTRIBUTES {
+        if let Some(value) = elem.attr(attr) {
+            builder = builder.attr(*attr, value);
+        }
+    }
+    if let Some(class) = class {
+        builder = builder.attr("class", class);
+    }
+    builder
+}
+
+fn image(image: &Element) -> Element {
+    let src = match href(image) {
+        Some(href) if href.starts_with('#') => image_href(&href[1..]),
+        Some(href) => href.to_string(),
+        None => String::new(),
+    };
+    start("img", image, None)
+        .attr("src", src)
+        .attr("alt", image.attr("alt").unwrap_or_default())
+        .build()
+}
+
+fn image_href(id: &str) -> String {
+    format!("images/{}", id)
+}
+
+/// Finds the `href` of links and images, whatever prefix the XLink namespace was given.
+fn href(elem: &Element) -> Option<&str> {
+    elem.attrs()
+        .find(|(name, _)| *name == "href" || name.ends_with(":href"))
+        .map(|(_, value)| value)
+}
+
+fn child<'a>(elem: &'a Element, name: &str) -> Option<&'a Element> {
+    elem.children().find(|c| c.name() == name)
+}
+
+fn field(parent: Option<&Element>, name: &str) -> Option<String> {
+    parent
+        .and_then(|p| child(p, name))
+        .map(|c| text_content(c).trim().to_string())
+        .filter(|t| !t.is_empty())
+}
+
+fn author_name(author: &Element) -> Option<String> {
+    let names: Vec<String> = ["first-name", "middle-name", "last-name"]
+        .iter()
+        .filter_map(|n| field(Some(author), n))
+        .collect();
+    if names.is_empty() {
+        field(Some(author), "nickname")
+    } else {
+        Some(names.join(" "))
+    }
+}
+
+/// The text of the `title` of a section or body, with its paragraphs on a single line.
+fn title_text(elem: &Element) -> Option<String> {
+    let lines: Vec<String> = child(elem, "title")?
+        .children()
+        .map(|p| text_content(p).trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect();
+    Some(lines.join(" ")).filter(|t| !t.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::Epub;
+    use crate::test_utils;
+
+    const BOOK: &str = r##"<?xml version="1.0" encoding="utf-8"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0"
+  xmlns:l="http://www.w3.org/1999/xlink">
+  <description>
+    <title-info>
+      <author><first-name>Anton</first-name><last-name>Chekhov</last-name></author>
+      <book-title>Stories</book-title>
+      <lang>en</lang>
+      <coverpage><image l:href="#cover.png"/></coverpage>
+    </title-info>
+    <document-info><id>stories-1</id></document-info>
+  </description>
+  <body>
+    <title><p>Anton Chekhov</p><p>Stories</p></title>
+    <epigraph><p>Brevity is the sister of talent.</p></epigraph>
+    <section id="s1">
+      <title><p>The Lady</p><p>with the Dog</p></title>
+      <p>It was said<a l:href="#n1" type="note">1</a> that a <emphasis>new</emphasis> person
+        had appeared.</p>
+      <empty-line/>
+      <section><title><p>II</p></title><p>A week had passed.</p></section>
+    </section>
+    <section>
+      <p>See <a l:href="#s1">the first story</a>.</p>
+    </section>
+  </body>
+  <body name="notes">
+    <title><p>Notes</p></title>
+    <section id="n1"><title><p>1</p></title><p>On the promenade.</p></section>
+  </body>
+  <binary id="cover.png" content-type="image/png">cG5n
+  </binary>
+</FictionBook>"##;
+
+    #[test]
+    fn chapters_and_toc() -> Result<()> {
+        let mut epub = Epub::new(to_epub(BOOK.as_bytes())?)?;
+        let toc = epub.toc()?;
+        let titles: Vec<_> = toc.iter().map(|i| i.text.as_str()).collect();
+        assert_eq!(
+            vec!["Stories", "The Lady with the Dog", "Section 2", "Notes"],
+            titles
+        );
+        let lead = epub.chapter(0)?;
+        assert!(lead.contains("data:image/png;base64,cG5n"));
+        assert!(lead.contains("<h1>Anton Chekhov<br/>Stories</h1>"));
+        assert!(lead.contains("<blockquote class=\"epigraph\">"));
+        let story = epub.next_chapter()?;
+        assert!(story.contains("<section id=\"s1\">"));
+        assert!(story.contains("<h1>The Lady<br/>with the Dog</h1>"));
+        assert!(story.contains("<em>new</em>"));
+        assert!(story.contains("<h2>II</h2>"));
+        assert!(epub.next_chapter()?.contains("href=\"chapter2.xhtml#s1\""));
+        Ok(())
+    }
+
+    #[test]
+    fn notes() -> Result<()> {
+        let mut epub = Epub::new(to_epub(BOOK.as_bytes())?)?;
+        let story = epub.chapter(1)?;
+        assert!(story.contains("href=\"chapter4.xhtml#n1\""));
+        assert!(story.contains("epub:type=\"noteref\""));
+        let note = epub.note("chapter4.xhtml#n1", true)?.ok_or("no note")?;
+        assert!(note.contains("On the promenade."));
+        assert!(!note.contains("<h1>"));
+        Ok(())
+    }
+
+    #[test]
+    fn zipped() -> Result<()> {
+        let bytes = test_utils::build_zip(&[("stories.fb2", BOOK.as_bytes())]);
+        let mut epub = Epub::new(to_epub(&bytes)?)?;
+        assert_eq!(4, epub.toc()?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn no_body() {
+        let bodiless = b"<?xml version=\"1.0\"?>\n<FictionBook><description/></FictionBook>";
+        assert!(to_epub(bodiless).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//! Tells EPUBs apart from the other files people try to open with leedor, by looking at what's
+//! inside them rather than at their names.
+
+use crate::comic;
+use crate::encoding;
+use std::fmt;
+use std::io::{Cursor, Read};
+use zip::ZipArchive;
+
+const EPUB_MIMETYPE: &str = "application/epub+zip";
+const OPEN_DOCUMENT_MIMETYPE_PREFIX: &str = "application/vnd.oasis.opendocument";
+/// How far into a file to look for the root element of an FB2 book, or for binary data.
+const SNIFF_LEN: usize = 1024;
+/// Files comic book archives commonly carry besides their pages.
+const COMIC_EXTRAS: &[&str] = &["comicinfo.xml", "thumbs.db", ".ds_store"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Epub,
+    /// A zip with a missing, unreadable or wrong `mimetype` that still has an EPUB container.
+    /// It can usually be read, but other readers may refuse it.
+    BrokenEpub,
+    /// A comic book archive: a zip of page images.
+    Cbz,
+    /// A FictionBook 2 book, possibly zipped.
+    Fb2,
+    /// Plain text or Markdown.
+    Text,
+    Docx,
+    OpenDocument,
+    /// Any other zip archive.
+    Zip,
+    Unknown,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Format::Epub => "an EPUB book",
+            Format::BrokenEpub => "an EPUB book with a broken mimetype file",
+            Format::Cbz => "a comic book archive",
+            Format::Fb2 => "a FictionBook book",
+            Format::Text => "a text file",
+            Format::Docx => "a Word document",
+            Format::OpenDocument => "an OpenDocument file",
+            Format::Zip => "a zip archive",
+            Format::Unknown => "a file of an unknown format",
+        };
+        write!(fmt, "{}", name)
+    }
+}
+
+impl Format {
+    /// Whether books of this format can be opened as EPUBs, even if with problems.
+    pub fn is_epub(self) -> bool {
+        self == Format::Epub || self == Format::BrokenEpub
+    }
+}
+
+/// Sniffs the format of `bytes`. Zips with an EPUB `mimetype` entry are EPUBs, while those with
+/// a missing, unreadable or wrong one that still have a container.xml are broken EPUBs, so that
+/// they open with a warning rather than not at all.
+pub fn detect(bytes: &[u8]) -> Format {
+    let mut zip = match ZipArchive::new(Cursor::new(bytes)) {
+        Ok(zip) => zip,
+        Err(_) if is_fb2(bytes) => return Format::Fb2,
+        Err(_) if is_text(bytes) => return Format::Text,
+        Err(_) => return Format::Unknown,
+    };
+    if let Ok(mut mimetype_file) = zip.by_name("mimetype") {
+        let mut mimetype = String::new();
+        if mimetype_file.read_to_string(&mut mimetype).is_ok() {
+            let mimetype = mimetype.trim();
+            if mimetype == EPUB_MIMETYPE {
+                return Format::Epub;
+            } else if mimetype.starts_with(OPEN_DOCUMENT_MIMETYPE_PREFIX) {
+                return Format::OpenDocument;
+            }
+        }
+    }
+    let names: Vec<String> = (0..zip.len())
+        .filter_map(|i| zip.by_index(i).ok().map(|f| f.name().to_string()))
+        .filter(|n| !n.ends_with('/'))
+        .collect();
+    let has_file = |name: &str| names.iter().any(|n| n == name);
+    if has_file("META-INF/container.xml") {
+        Format::BrokenEpub
+    } else if has_file("[Content_Types].xml") && has_file("word/document.xml") {
+        Format::Docx
+    } else if names.len() == 1 && names[0].to_lowercase().ends_with(".fb2") {
+        Format::Fb2
+    } else if is_comic(&names) {
+        Format::Cbz
+    } else {
+        Format::Zip
+    }
+}
+
+fn is_fb2(bytes: &[u8]) -> bool {
+    String::from_utf8_lossy(sniffed(bytes)).contains("<FictionBook")
+}
+
+/// Whether `bytes` start with text, other than markup, without any control characters that
+/// only binary files have. Text that isn't UTF-8 is read the way `encoding::decode` reads it,
+/// i.e. as UTF-16 when it has a byte order mark and as Windows-1252 otherwise.
+fn is_text(bytes: &[u8]) -> bool {
+    let start = sniffed(bytes);
+    // PDFs start like text, followed by a comment of high bytes that would pass for Windows-1252
+    if start.starts_with(b"%PDF-") {
+        return false;
+    }
+    let text = match std::str::from_utf8(start) {
+        Ok(text) => text.to_string(),
+        // a character cut in half at the end of the sniffed bytes
+        Err(err) if err.error_len().is_none() => {
+            String::from_utf8_lossy(&start[..err.valid_up_to()]).into_owned()
+        }
+        // byte order marks are never valid UTF-8
+        Err(_) => encoding::decode(start),
+    };
+    let is_binary = text
+        .chars()
+        .any(|c| c.is_control() && !"\t\n\r\x0c".contains(c));
+    !text.trim().is_empty() && !is_binary && !text.trim_start().starts_with('<')
+}
+
+fn sniffed(bytes: &[u8]) -> &[u8] {
+    &bytes[..bytes.len().min(SNIFF_LEN)]
+}
+
+fn is_comic(names: &[String]) -> bool {
+    let mut image_count = 0;
+    for name in names {
+        let name = name.to_lowercase();
+        let file_name = name.rsplit('/').next().unwrap_or_default();
+        if comic::page_media_type(file_name).is_some() {
+            image_count += 1;
+        } else if !COMIC_EXTRAS.contains(&file_name) {
+            return false;
+        }
+    }
+    image_count > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn epub() {
+        let bytes = test_utils::build_single_chapter_epub("<html/>");
+        assert_eq!(Format::Epub, detect(&bytes));
+        let bytes = test_utils::build_zip(&[(
+            "META-INF/container.xml",
+            test_utils::CONTAINER_XML.as_bytes(),
+        )]);
+        assert_eq!(Format::BrokenEpub, detect(&bytes));
+        let container = test_utils::CONTAINER_XML.as_bytes();
+        let bytes = test_utils::build_zip(&[
+            ("mimetype", b"application/epub"),
+            ("META-INF/container.xml", container),
+        ]);
+        assert_eq!(Format::BrokenEpub, detect(&bytes));
+        assert!(Format::BrokenEpub.is_epub());
+    }
+
+    #[test]
+    fn other_formats() {
+        assert_eq!(Format::Unknown, detect(b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n"));
+        assert_eq!(Format::Unknown, detect(b"<!DOCTYPE html><html></html>"));
+        assert_eq!(
+            Format::Text,
+            detect("# Walden\n\nBy Thoreau \u{2014} 1854\n".as_bytes())
+        );
+        let utf16: Vec<u8> = "\u{feff}Walden\n\nBy Thoreau\n"
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes().to_vec())
+            .collect();
+        assert_eq!(Format::Text, detect(&utf16));
+        assert_eq!(Format::Text, detect(b"Caf\xe9 au lait\n"));
+        assert_eq!(Format::Unknown, detect(b"\xff\xd8\xff\xe0\x00\x10JFIF\x00"));
+        let fb2 =
+            b"<?xml version=\"1.0\"?>\n<FictionBook xmlns:l=\"http://www.w3.org/1999/xlink\"/>";
+        assert_eq!(Format::Fb2, detect(fb2));
+        let fb2_zip = test_utils::build_zip(&[("book.fb2", fb2)]);
+        assert_eq!(Format::Fb2, detect(&fb2_zip));
+        let cbz = test_utils::build_zip(&[
+            ("Comic/01.jpg", b"jpg"),
+            ("Comic/02.PNG", b"png"),
+            ("ComicInfo.xml", b"<ComicInfo/>"),
+        ]);
+        assert_eq!(Format::Cbz, detect(&cbz));
+        let docx = test_utils::build_zip(&[
+            ("[Content_Types].xml", b"<Types/>"),
+            ("word/document.xml", b"<w:document/>"),
+        ]);
+        assert_eq!(Format::Docx, detect(&docx));
+        let odt = test_utils::build_zip(&[
+            ("mimetype", b"application/vnd.oasis.opendocument.text"),
+            ("content.xml", b"<office:document-content/>"),
+        ]);
+        assert_eq!(Format::OpenDocument, detect(&odt));
+        let zip = test_utils::build_zip(&[("notes.txt", b"notes"), ("cover.jpg", b"jpg")]);
+        assert_eq!(Format::Zip, detect(&zip));
+        let zip = test_utils::build_zip(&[("mimetype", b"application/zip"), ("a.txt", b"a")]);
+        assert_eq!(Format::Zip, detect(&zip));
+    }
+}
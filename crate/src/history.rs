@@ -0,0 +1,83 @@
+//! Locations visited by following links, for going back and forward like a browser does.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub spine_idx: usize,
+    pub fragment: Option<String>,
+}
+
+impl Location {
+    pub fn new(spine_idx: usize, fragment: Option<&str>) -> Location {
+        let fragment = fragment.map(String::from);
+        Location {
+            spine_idx,
+            fragment,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<Location>,
+    position: usize,
+}
+
+impl History {
+    /// Adds a location right after the current one, dropping the locations ahead of it. Nothing
+    /// changes when `location` is the current one.
+    pub fn push(&mut self, location: Location) {
+        if self.current() == Some(&location) {
+            return;
+        }
+        if !self.entries.is_empty() {
+            self.entries.truncate(self.position + 1);
+        }
+        self.entries.push(location);
+        self.position = self.entries.len() - 1;
+    }
+
+    /// Updates the current location, e.g. after turning to the next chapter.
+    pub fn replace(&mut self, location: Location) {
+        match self.entries.get_mut(self.position) {
+            Some(entry) => *entry = location,
+            None => self.push(location),
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn current(&self) -> Option<&Location> {
+        self.entries.get(self.position)
+    }
+
+    pub fn go_to(&mut self, position: usize) -> Option<&Location> {
+        if position >= self.entries.len() {
+            return None;
+        }
+        self.position = position;
+        self.entries.get(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn back_and_forward() {
+        let mut history = History::default();
+        history.replace(Location::new(0, None));
+        history.push(Location::new(3, Some("note-1")));
+        history.push(Location::new(1, None));
+        history.push(Location::new(1, None));
+        assert_eq!(2, history.position());
+        assert_eq!(Some(&Location::new(3, Some("note-1"))), history.go_to(1));
+        assert_eq!(Some(&Location::new(0, None)), history.go_to(0));
+        assert_eq!(None, history.go_to(3));
+        history.push(Location::new(2, None));
+        assert_eq!(None, history.go_to(2));
+        assert_eq!(1, history.position());
+    }
+}
@@ -0,0 +1,183 @@
+use crate::encoding;
+use crate::entities;
+use crate::error::Result;
+use minidom::Element;
+use quick_xml::events::{BytesStart, Event};
+use std::borrow::Cow;
+use std::io::{BufReader, Read};
+
+const XHTML_NS: &str = "http://www.w3.org/1999/xhtml";
+pub const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Parses tag soup into an `Element` tree, for content documents that aren't well-formed XML.
+/// Void elements don't need to be closed, mismatched end tags close every element opened after
+/// the matching start tag and unknown entities are kept as text.
+pub fn parse_html<R: Read>(contents: R) -> Result<Element> {
+    let mut bytes = vec![];
+    BufReader::new(contents).read_to_end(&mut bytes)?;
+    let text = encoding::decode(&bytes);
+    let mut reader = quick_xml::Reader::from_reader(text.as_bytes());
+    reader.check_end_names(false);
+    let mut stack = vec![Element::bare("document")];
+    let mut buf = vec![];
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(ref e) => {
+                let elem = build_element(e);
+                if VOID_ELEMENTS.contains(&elem.name()) {
+                    append_child(&mut stack, elem);
+                } else {
+                    stack.push(elem);
+                }
+            }
+            Event::Empty(ref e) => {
+                let elem = build_element(e);
+                append_child(&mut stack, elem);
+            }
+            Event::End(ref e) => {
+                let name = String::from_utf8_lossy(e.name()).to_lowercase();
+                let open_idx = stack.iter().rposition(|elem| qualified_name(elem) == name);
+                if let Some(idx) = open_idx.filter(|idx| *idx > 0) {
+                    while stack.len() > idx {
+                        close_element(&mut stack);
+                    }
+                }
+            }
+            Event::Text(ref e) => {
+                let text = unescape(&String::from_utf8_lossy(e.escaped()));
+                if !text.is_empty() {
+                    stack
+                        .last_mut()
+                        .expect("empty stack")
+                        .append_text_node(text);
+                }
+            }
+            Event::CData(ref e) => {
+                let text = String::from_utf8_lossy(e.escaped()).into_owned();
+                stack
+                    .last_mut()
+                    .expect("empty stack")
+                    .append_text_node(text);
+            }
+            Event::Eof => break,
+            Event::Comment(_) | Event::Decl(_) | Event::PI(_) | Event::DocType(_) => (),
+        }
+        buf.clear();
+    }
+    while stack.len() > 1 {
+        close_element(&mut stack);
+    }
+    let document = stack.pop().expect("empty stack");
+    if let Some(html) = document.children().find(|c| c.name() == "html") {
+        return Ok(html.clone());
+    }
+    let mut html = Element::builder("html").ns(XHTML_NS).build();
+    for node in document.nodes() {
+        html.append_node(node.clone());
+    }
+    Ok(html)
+}
+
+fn build_element(event: &BytesStart) -> Element {
+    let name = String::from_utf8_lossy(event.name()).to_lowercase();
+    let mut builder = Element::builder(&name);
+    if name == "html" {
+        builder = builder.ns(XHTML_NS);
+    }
+    let mut attributes = event.attributes();
+    attributes.with_checks(false);
+    for attr in attributes.filter_map(|a| a.ok()) {
+        let key = String::from_utf8_lossy(attr.key).to_lowercase();
+        let value = unescape(&String::from_utf8_lossy(&attr.value));
+        if key == "xmlns" {
+            builder = builder.ns(value);
+        } else if !key.starts_with("xmlns:") {
+            builder = builder.attr(key, value);
+        }
+    }
+    builder.build()
+}
+
+fn append_child(stack: &mut [Element], elem: Element) {
+    stack.last_mut().expect("empty stack").append_child(elem);
+}
+
+fn close_element(stack: &mut Vec<Element>) {
+    let elem = stack.pop().expect("empty stack");
+    append_child(stack, elem);
+}
+
+fn qualified_name(elem: &Element) -> Cow<'_, str> {
+    match elem.prefix() {
+        Some(prefix) => Cow::Owned(format!("{}:{}", prefix, elem.name())),
+        None => Cow::Borrowed(elem.name()),
+    }
+}
+
+/// Replaces character and entity references, leaving anything it doesn't recognize untouched.
+fn unescape(raw: &str) -> String {
+    let mut unescaped = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(amp_idx) = rest.find('&') {
+        unescaped.push_str(&rest[..amp_idx]);
+        rest = &rest[amp_idx..];
+        let decoded = rest.find(';').and_then(|semicolon_idx| {
+            entities::lookup(&rest[1..semicolon_idx]).map(|c| (c, semicolon_idx))
+        });
+        match decoded {
+            Some((c, semicolon_idx)) => {
+                unescaped.push(c);
+                rest = &rest[semicolon_idx + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unclosed_void_elements() -> Result<()> {
+        let contents = b"<html><body><p>one<br>two<img src='a.png'></p></body></html>";
+        let html = parse_html(&contents[..])?;
+        let body = html
+            .children()
+            .find(|c| c.name() == "body")
+            .ok_or("no body")?;
+        let p = body.children().next().ok_or("no p")?;
+        assert_eq!(
+            vec!["br", "img"],
+            p.children().map(|c| c.name()).collect::<Vec<_>>()
+        );
+        assert_eq!("onetwo", p.text());
+        Ok(())
+    }
+
+    #[test]
+    fn unescaped_ampersands_and_unknown_entities() -> Result<()> {
+        let html = parse_html(&b"<html><body>Tom & Jerry&nbsp;&bogus;&#233;</body></html>"[..])?;
+        let body = html.children().next().ok_or("no body")?;
+        assert_eq!("Tom & Jerry\u{a0}&bogus;\u{e9}", body.text());
+        Ok(())
+    }
+
+    #[test]
+    fn mismatched_end_tags() -> Result<()> {
+        let html = parse_html(&b"<div><p><b>bold</p>after</i></div>"[..])?;
+        assert_eq!("html", html.name());
+        assert_eq!(Some(XHTML_NS.to_string()), html.ns());
+        let div = html.children().next().ok_or("no div")?;
+        assert_eq!("after", div.text());
+        Ok(())
+    }
+}
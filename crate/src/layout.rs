@@ -0,0 +1,233 @@
+//! Rendering properties from the EPUB 3 `rendition:` vocabulary, set book-wide through OPF
+//! `meta` elements and overridden per spine item through `itemref` properties, along with the
+//! spine's page progression direction and the book's writing mode.
+
+/// The spine's `page-progression-direction`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Ltr,
+    Rtl,
+}
+
+impl Direction {
+    /// Returns `None` for `default`, which leaves the direction up to the writing mode.
+    pub fn from_attr(value: Option<&str>) -> Option<Direction> {
+        match value {
+            Some("ltr") => Some(Direction::Ltr),
+            Some("rtl") => Some(Direction::Rtl),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WritingMode {
+    HorizontalTb,
+    VerticalLr,
+    VerticalRl,
+}
+
+impl WritingMode {
+    pub fn from_value(value: &str) -> Option<WritingMode> {
+        match value.trim().to_lowercase().as_str() {
+            "horizontal-tb" | "lr-tb" | "lr" => Some(WritingMode::HorizontalTb),
+            "vertical-lr" | "tb-lr" => Some(WritingMode::VerticalLr),
+            "vertical-rl" | "tb-rl" | "tb" => Some(WritingMode::VerticalRl),
+            _ => None,
+        }
+    }
+
+    pub fn is_vertical(self) -> bool {
+        self != WritingMode::HorizontalTb
+    }
+
+    /// The page progression direction implied by this writing mode.
+    pub fn direction(self) -> Direction {
+        match self {
+            WritingMode::VerticalRl => Direction::Rtl,
+            WritingMode::HorizontalTb | WritingMode::VerticalLr => Direction::Ltr,
+        }
+    }
+}
+
+/// Finds the writing mode a stylesheet sets on the root or body element, through the standard
+/// property or its `-epub-` and `-webkit-` prefixed forms.
+pub fn css_writing_mode(css: &str) -> Option<WritingMode> {
+    let mut writing_mode = None;
+    for rule in css.split('}') {
+        let mut parts = rule.splitn(2, '{');
+        let (selectors, declarations) = match (parts.next(), parts.next()) {
+            (Some(s), Some(d)) => (s.to_lowercase(), d),
+            _ => continue,
+        };
+        let targets_root = selectors
+            .split(|c: char| !c.is_alphanumeric() && c != '-' && c != ':')
+            .any(|s| s == "html" || s == "body" || s == ":root");
+        if !targets_root {
+            continue;
+        }
+        for declaration in declarations.split(';') {
+            let mut parts = declaration.splitn(2, ':');
+            let property = parts.next().unwrap_or_default().trim().to_lowercase();
+            let value = parts.next().unwrap_or_default().replace("!important", "");
+            let property = property
+                .trim_start_matches("-epub-")
+                .trim_start_matches("-webkit-");
+            if property == "writing-mode" {
+                writing_mode = WritingMode::from_value(&value).or(writing_mode);
+            }
+        }
+    }
+    writing_mode
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    Reflowable,
+    PrePaginated,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Orientation {
+    Auto,
+    Landscape,
+    Portrait,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Spread {
+    Auto,
+    Both,
+    Landscape,
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PageSpread {
+    Center,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LayoutProperties {
+    pub layout: Layout,
+    pub orientation: Orientation,
+    pub spread: Spread,
+    pub page_spread: Option<PageSpread>,
+}
+
+impl Default for LayoutProperties {
+    fn default() -> LayoutProperties {
+        LayoutProperties {
+            layout: Layout::Reflowable,
+            orientation: Orientation::Auto,
+            spread: Spread::Auto,
+            page_spread: None,
+        }
+    }
+}
+
+impl LayoutProperties {
+    /// Applies a book-wide `<meta property="rendition:...">` value.
+    pub fn set_meta(&mut self, property: &str, value: &str) {
+        let property = format!("{}-{}", property, value.trim());
+        self.set_property(&property);
+    }
+
+    /// Applies a spine `itemref` property such as `rendition:layout-pre-paginated` or
+    /// `page-spread-left`. Unknown properties are ignored.
+    pub fn set_property(&mut self, property: &str) {
+        match property {
+            "rendition:layout-pre-paginated" => self.layout = Layout::PrePaginated,
+            "rendition:layout-reflowable" => self.layout = Layout::Reflowable,
+            "rendition:orientation-auto" => self.orientation = Orientation::Auto,
+            "rendition:orientation-landscape" => self.orientation = Orientation::Landscape,
+            "rendition:orientation-portrait" => self.orientation = Orientation::Portrait,
+            "rendition:spread-auto" => self.spread = Spread::Auto,
+            // `portrait` is deprecated and means the same as `both`
+            "rendition:spread-both" | "rendition:spread-portrait" => self.spread = Spread::Both,
+            "rendition:spread-landscape" => self.spread = Spread::Landscape,
+            "rendition:spread-none" => self.spread = Spread::None,
+            "page-spread-left" | "rendition:page-spread-left" => {
+                self.page_spread = Some(PageSpread::Left)
+            }
+            "page-spread-right" | "rendition:page-spread-right" => {
+                self.page_spread = Some(PageSpread::Right)
+            }
+            "rendition:page-spread-center" => self.page_spread = Some(PageSpread::Center),
+            _ => (),
+        }
+    }
+
+    /// Whether two pages should be shown side by side in a viewport with this orientation.
+    pub fn shows_spread(&self, landscape: bool) -> bool {
+        match self.spread {
+            Spread::Both => true,
+            Spread::Auto | Spread::Landscape => landscape,
+            Spread::None => false,
+        }
+    }
+}
+
+/// Parses the dimensions out of a viewport `meta` element's content, e.g.
+/// `width=1200, height=1600`.
+pub fn parse_viewport(content: &str) -> Option<(u32, u32)> {
+    let mut width = None;
+    let mut height = None;
+    for pair in content.split(&[',', ';'][..]) {
+        let mut parts = pair.splitn(2, '=').map(str::trim);
+        match (parts.next(), parts.next()) {
+            (Some("width"), Some(value)) => width = value.parse().ok(),
+            (Some("height"), Some(value)) => height = value.parse().ok(),
+            _ => (),
+        }
+    }
+    match (width, height) {
+        (Some(w), Some(h)) => Some((w, h)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn itemref_properties_override_meta() {
+        let mut props = LayoutProperties::default();
+        props.set_meta("rendition:layout", "pre-paginated");
+        props.set_meta("rendition:spread", "none");
+        props.set_property("rendition:spread-both");
+        props.set_property("page-spread-right");
+        assert_eq!(Layout::PrePaginated, props.layout);
+        assert_eq!(Spread::Both, props.spread);
+        assert_eq!(Some(PageSpread::Right), props.page_spread);
+        assert!(props.shows_spread(false));
+    }
+
+    #[test]
+    fn writing_mode_from_css() {
+        let css = "p { writing-mode: vertical-lr; }\n\
+                   html, body { margin: 0; -epub-writing-mode: vertical-rl !important; }";
+        assert_eq!(Some(WritingMode::VerticalRl), css_writing_mode(css));
+        assert_eq!(
+            None,
+            css_writing_mode(".caption { writing-mode: vertical-rl }")
+        );
+        assert_eq!(Direction::Rtl, WritingMode::VerticalRl.direction());
+    }
+
+    #[test]
+    fn viewport() {
+        assert_eq!(
+            Some((1200, 1600)),
+            parse_viewport("width=1200, height=1600")
+        );
+        assert_eq!(
+            Some((600, 800)),
+            parse_viewport("height = 800; width = 600")
+        );
+        assert_eq!(None, parse_viewport("width=device-width, initial-scale=1"));
+    }
+}
@@ -1,6 +1,26 @@
+mod builder;
+mod cfi;
+mod comic;
+mod document;
+mod encoding;
+mod encryption;
+mod entities;
 mod epub;
 mod error;
+mod export;
+mod fb2;
+mod format;
+mod history;
+mod html;
+mod layout;
+mod markdown;
+mod progress;
+mod sanitize;
+#[cfg(test)]
+mod test_utils;
+mod text;
 mod utils;
+mod validate;
 mod web;
 mod xml;
 
@@ -13,4 +33,7 @@ cfg_if! {
     }
 }
 
+pub use document::{open, Document, EpubFeatures, Metadata};
+pub use export::export_html;
+pub use validate::{validate, Diagnostic, Severity};
 pub use web::run;
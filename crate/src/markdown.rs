@@ -0,0 +1,396 @@
+//! A small Markdown to XHTML converter covering what books written in Markdown use: headings,
+//! paragraphs, block quotes, lists, code, rules, emphasis, links and images. Nested lists, tables
+//! and HTML blocks aren't supported; their text comes out as paragraphs.
+
+use crate::xml::text_content;
+use minidom::{Element, ElementBuilder};
+
+const FENCES: &[&str] = &["```", "~~~"];
+
+pub struct Heading {
+    /// The index of the line the heading starts at.
+    pub line_idx: usize,
+    pub level: usize,
+    /// The heading's text, without any inline markup.
+    pub text: String,
+}
+
+/// Guesses whether `text` is Markdown rather than plain text, by looking for headings and code
+/// fences, which plain text rarely has.
+pub fn is_markdown(text: &str) -> bool {
+    let lines: Vec<&str> = text.lines().collect();
+    !headings(&lines).is_empty() || lines.iter().any(|l| is_fence(l))
+}
+
+/// Finds the ATX (`# Title`) and setext (`Title` underlined with `=` or `-`) headings in
+/// `lines`, skipping those in code blocks.
+pub fn headings(lines: &[&str]) -> Vec<Heading> {
+    let mut headings = vec![];
+    let mut in_fence = false;
+    for (idx, line) in lines.iter().enumerate() {
+        if is_fence(line) {
+            in_fence = !in_fence;
+        }
+        if in_fence {
+            continue;
+        }
+        let heading = atx_heading(line).or_else(|| {
+            let after_blank = idx == 0 || lines[idx - 1].trim().is_empty();
+            let underline = lines.get(idx + 1).and_then(|u| setext_level(u));
+            underline
+                .filter(|_| after_blank && !line.trim().is_empty() && !starts_block(line))
+                .map(|level| (level, line.trim()))
+        });
+        if let Some((level, text)) = heading {
+            headings.push(Heading {
+                line_idx: idx,
+                level,
+                text: text_content(&inline(text, Element::builder("span")).build()),
+            });
+        }
+    }
+    headings
+}
+
+/// Appends the blocks in `lines` to `builder`.
+pub fn render(lines: &[&str], mut builder: ElementBuilder) -> ElementBuilder {
+    let mut idx = 0;
+    while idx < lines.len() {
+        let line = lines[idx];
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            idx += 1;
+        } else if let Some((level, text)) = atx_heading(line) {
+            builder = builder.append(inline(text, Element::builder(heading_name(level))).build());
+            idx += 1;
+        } else if is_fence(line) {
+            let end = lines[idx + 1..]
+                .iter()
+                .position(|l| is_fence(l))
+                .map(|i| idx + 1 + i)
+                .unwrap_or_else(|| lines.len());
+            builder = builder.append(code_block(&lines[idx + 1..end], ""));
+            idx = end + 1;
+        } else if is_rule(trimmed) {
+            builder = builder.append(Element::builder("hr").build());
+            idx += 1;
+        } else if trimmed.starts_with('>') {
+            let len = lines[idx..]
+                .iter()
+                .take_while(|l| l.trim_start().starts_with('>'))
+                .count();
+            let quoted: Vec<&str> = lines[idx..idx + len]
+                .iter()
+                .map(|l| {
+                    let l = &l.trim_start()[1..];
+                    if l.starts_with(' ') {
+                        l.split_at(1).1
+                    } else {
+                        l
+                    }
+                })
+                .collect();
+            builder = builder.append(render(&quoted, Element::builder("blockquote")).build());
+            idx += len;
+        } else if let Some(ordered) = list_item(line).map(|(ordered, _)| ordered) {
+            let (list, len) = list(&lines[idx..], ordered);
+            builder = builder.append(list);
+            idx += len;
+        } else if line.starts_with("    ") || line.starts_with('\t') {
+            let len = lines[idx..]
+                .iter()
+                .take_while(|l| l.trim().is_empty() || l.starts_with("    ") || l.starts_with('\t'))
+                .count();
+            builder = builder.append(code_block(&lines[idx..idx + len], "    "));
+            idx += len;
+        } else {
+            let len = 1 + lines[idx + 1..]
+                .iter()
+                .take_while(|l| {
+                    !l.trim().is_empty() && !starts_block(l) && setext_level(l).is_none()
+                })
+                .count();
+            let underline = lines.get(idx + len).and_then(|l| setext_level(l));
+            let (name, next_idx) = match underline {
+                Some(level) => (heading_name(level), idx + len + 1),
+                None => ("p".to_string(), idx + len),
+            };
+            let text: Vec<&str> = lines[idx..idx + len].iter().map(|l| l.trim()).collect();
+            builder = builder.append(inline(&text.join("\n"), Element::builder(name)).build());
+            idx = next_idx;
+        }
+    }
+    builder
+}
+
+/// Builds a list out of the items starting at `lines[0]`, returning it along with the number of
+/// lines it takes up. Lines that don't start an item continue the previous one.
+fn list(lines: &[&str], ordered: bool) -> (Element, usize) {
+    let mut items: Vec<Vec<&str>> = vec![];
+    let mut idx = 0;
+    while idx < lines.len() {
+        let line = lines[idx];
+        if line.trim().is_empty() {
+            let next_item = lines.get(idx + 1).and_then(|l| list_item(l));
+            if next_item.map(|(o, _)| o) != Some(ordered) {
+                break;
+            }
+        } else if let Some((item_ordered, text)) = list_item(line) {
+            if item_ordered != ordered {
+                break;
+            }
+            items.push(vec![text]);
+        } else if starts_block(line) {
+            break;
+        } else if let Some(item) = items.last_mut() {
+            item.push(line.trim());
+        }
+        idx += 1;
+    }
+    let mut builder = Element::builder(if ordered { "ol" } else { "ul" });
+    for item in items {
+        builder = builder.append(inline(&item.join("\n"), Element::builder("li")).build());
+    }
+    (builder.build(), idx)
+}
+
+fn code_block(lines: &[&str], indent: &str) -> Element {
+    let code: Vec<&str> = lines
+        .iter()
+        .map(|l| {
+            if l.starts_with(indent) {
+                l.split_at(indent.len()).1
+            } else {
+                l.trim_start_matches('\t')
+            }
+        })
+        .collect();
+    let code = Element::builder("code")
+        .append(code.join("\n").trim_end().to_string())
+        .build();
+    Element::builder("pre").append(code).build()
+}
+
+/// Appends the text of a block to `builder`, turning emphasis, code spans, links and images
+/// into elements.
+fn inline(text: &str, mut builder: ElementBuilder) -> ElementBuilder {
+    let mut plain = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let span = match c {
+            '\\' => {
+                let escaped = rest[1..]
+                    .chars()
+                    .next()
+                    .filter(|e| e.is_ascii_punctuation());
+                if let Some(escaped) = escaped {
+                    plain.push(escaped);
+                    rest = &rest[1 + escaped.len_utf8()..];
+                    continue;
+                }
+                None
+            }
+            '`' => rest[1..].find('`').map(|end| {
+                let code = Element::builder("code")
+                    .append(rest[1..=end].to_string())
+                    .build();
+                (code, end + 2)
+            }),
+            '!' if rest[1..].starts_with('[') => link(&rest[1..]).map(|(alt, url, len)| {
+                let img = Element::builder("img")
+                    .attr("src", url)
+                    .attr("alt", alt)
+                    .build();
+                (img, len + 1)
+            }),
+            '[' => link(rest).map(|(text, url, len)| {
+                let a = Element::builder("a").attr("href", url);
+                (inline(text, a).build(), len)
+            }),
+            '*' | '_' => {
+                let intraword = c == '_'
+                    && plain
+                        .chars()
+                        .last()
+                        .filter(|c| c.is_alphanumeric())
+                        .is_some();
+                if intraword {
+                    None
+                } else {
+                    emphasis(rest, c)
+                }
+            }
+            _ => None,
+        };
+        match span {
+            Some((elem, len)) => {
+                if !plain.is_empty() {
+                    builder = builder.append(plain.split_off(0));
+                }
+                builder = builder.append(elem);
+                rest = &rest[len..];
+            }
+            None => {
+                plain.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    if !plain.is_empty() {
+        builder = builder.append(plain);
+    }
+    builder
+}
+
+/// Parses `*em*` or `**strong**` at the start of `text`, returning the element and the length
+/// of its markup.
+fn emphasis(text: &str, delimiter: char) -> Option<(Element, usize)> {
+    let run = text.chars().take_while(|c| *c == delimiter).count().min(2);
+    let marker = &text[..run];
+    let inner_start = &text[run..];
+    if inner_start.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let end = inner_start
+        .match_indices(marker)
+        .map(|(i, _)| i)
+        .find(|i| *i > 0 && !inner_start[..*i].ends_with(char::is_whitespace))?;
+    let name = if run == 2 { "strong" } else { "em" };
+    let elem = inline(&inner_start[..end], Element::builder(name)).build();
+    Some((elem, run + end + run))
+}
+
+/// Parses `[text](url)` at the start of `text` into the text, the URL and the markup's length.
+/// A title after the URL is dropped.
+fn link(text: &str) -> Option<(&str, &str, usize)> {
+    let text_end = text.find("](")?;
+    let url_end = text_end + 2 + text[text_end + 2..].find(')')?;
+    let url = text[text_end + 2..url_end]
+        .split_whitespace()
+        .next()
+        .unwrap_or_default();
+    let url = url.trim_start_matches('<').trim_end_matches('>');
+    Some((&text[1..text_end], url, url_end + 1))
+}
+
+fn atx_heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    let rest = &line[level..];
+    if level == 0 || level > 6 || !(rest.is_empty() || rest.starts_with(char::is_whitespace)) {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+fn setext_level(line: &str) -> Option<usize> {
+    let line = line.trim();
+    if line.len() >= 3 && line.chars().all(|c| c == '=') {
+        Some(1)
+    } else if line.len() >= 3 && line.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+fn heading_name(level: usize) -> String {
+    format!("h{}", level.max(1).min(6))
+}
+
+fn is_fence(line: &str) -> bool {
+    FENCES.iter().any(|f| line.trim_start().starts_with(f))
+}
+
+fn is_rule(line: &str) -> bool {
+    let chars: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    ['-', '*', '_']
+        .iter()
+        .any(|r| chars.len() >= 3 && chars.chars().all(|c| c == *r))
+}
+
+/// Whether `line` starts a block other than a paragraph, which ends any paragraph before it.
+fn starts_block(line: &str) -> bool {
+    let trimmed = line.trim();
+    atx_heading(line).is_some()
+        || is_fence(line)
+        || is_rule(trimmed)
+        || trimmed.starts_with('>')
+        || list_item(line).is_some()
+}
+
+/// Parses a list item marker, returning whether the list is ordered and the item's text.
+fn list_item(line: &str) -> Option<(bool, &str)> {
+    let trimmed = line.trim_start();
+    for marker in &["- ", "* ", "+ "] {
+        if trimmed.starts_with(marker) && !is_rule(trimmed) {
+            return Some((false, trimmed[marker.len()..].trim()));
+        }
+    }
+    let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+    let after = &trimmed[digits..];
+    if digits > 0 && digits < 10 && (after.starts_with(". ") || after.starts_with(") ")) {
+        return Some((true, after[2..].trim()));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Result;
+
+    fn to_xhtml(markdown: &str) -> Result<String> {
+        let lines: Vec<&str> = markdown.lines().collect();
+        let mut bytes = vec![];
+        render(&lines, Element::builder("body"))
+            .build()
+            .write_to(&mut bytes)?;
+        Ok(String::from_utf8(bytes)?)
+    }
+
+    #[test]
+    fn blocks() -> Result<()> {
+        let markdown =
+            "# Title #\n\nSome *text*\nover lines.\n\nSub\n---\n\n> quoted\n> **text**\n\n\
+                        - one\n- two\n  continued\n\n1. first\n\n```\nlet x = 1 < 2;\n```\n\n***\n";
+        let xhtml = to_xhtml(markdown)?;
+        assert!(xhtml.contains("<h1>Title</h1>"));
+        assert!(xhtml.contains("<p>Some <em>text</em>\nover lines.</p>"));
+        assert!(xhtml.contains("<h2>Sub</h2>"));
+        assert!(xhtml.contains("<blockquote><p>quoted\n<strong>text</strong></p></blockquote>"));
+        assert!(xhtml.contains("<ul><li>one</li><li>two\ncontinued</li></ul>"));
+        assert!(xhtml.contains("<ol><li>first</li></ol>"));
+        assert!(xhtml.contains("<pre><code>let x = 1 &lt; 2;</code></pre>"));
+        assert!(xhtml.contains("<hr/>"));
+        Ok(())
+    }
+
+    #[test]
+    fn inline_markup() -> Result<()> {
+        let xhtml = to_xhtml(
+            "A [link](http://example.com \"Title\") to ![a cat](cat.png), `*code*`, \
+             snake_case_name, \\*not em\\* and 2 * 3 * 4.",
+        )?;
+        assert!(xhtml.contains("<a href=\"http://example.com\">link</a>"));
+        assert!(xhtml.contains("<img alt=\"a cat\" src=\"cat.png\"/>"));
+        assert!(xhtml.contains("<code>*code*</code>"));
+        assert!(xhtml.contains("snake_case_name, *not em* and 2 * 3 * 4."));
+        Ok(())
+    }
+
+    #[test]
+    fn heading_detection() {
+        let text = "Title\n=====\n\nText\n\n```\n# not a heading\n```\n\n## Part *Two*\n";
+        let lines: Vec<&str> = text.lines().collect();
+        let found: Vec<_> = headings(&lines)
+            .into_iter()
+            .map(|h| (h.line_idx, h.level, h.text))
+            .collect();
+        assert_eq!(
+            vec![(0, 1, "Title".to_string()), (9, 2, "Part Two".to_string())],
+            found
+        );
+        assert!(is_markdown(text));
+        assert!(!is_markdown("Just some\ntext - really.\n"));
+    }
+}
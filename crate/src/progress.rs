@@ -0,0 +1,139 @@
+//! Reading progress through a book, measured by the amount of text in each spine item.
+
+use minidom::Element;
+
+const SKIPPED_ELEMENTS: &[&str] = &["head", "script", "style"];
+/// Kana, CJK ideographs and their extensions.
+const CJK_RANGES: &[(u32, u32)] = &[
+    (0x3040, 0x30ff),
+    (0x3400, 0x4dbf),
+    (0x4e00, 0x9fff),
+    (0xf900, 0xfaff),
+    (0x20000, 0x2ffff),
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TextLength {
+    /// Characters other than whitespace.
+    pub chars: usize,
+    pub words: usize,
+}
+
+impl TextLength {
+    /// Measures the text in `elem`, leaving out the document head, scripts and stylesheets.
+    /// Chinese and Japanese text isn't split into words, so each of its characters counts as one.
+    pub fn of(elem: &Element) -> TextLength {
+        let mut length = TextLength::default();
+        for node in elem.nodes() {
+            if let Some(text) = node.as_text() {
+                for token in text.split_whitespace() {
+                    let cjk_chars = token.chars().filter(|c| is_cjk(*c)).count();
+                    let other_word = token.chars().any(|c| c.is_alphanumeric() && !is_cjk(c));
+                    length.chars += token.chars().count();
+                    length.words += cjk_chars + other_word as usize;
+                }
+            } else if let Some(child) = node.as_element() {
+                if !SKIPPED_ELEMENTS.contains(&child.name()) {
+                    let child_length = TextLength::of(child);
+                    length.chars += child_length.chars;
+                    length.words += child_length.words;
+                }
+            }
+        }
+        length
+    }
+}
+
+/// Whether `c` is a Chinese character or Japanese kana. Korean is written with spaces between
+/// words, so Hangul isn't included.
+fn is_cjk(c: char) -> bool {
+    let c = c as u32;
+    CJK_RANGES
+        .iter()
+        .any(|&(start, end)| start <= c && c <= end)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress {
+    pub item_idx: usize,
+    pub item_count: usize,
+    /// How much of the book's text comes before the reader's position, from 0 to 1.
+    pub fraction: f64,
+    pub words_left: usize,
+}
+
+impl Progress {
+    /// Locates a position `within_item` (from 0 to 1) of the way through spine item `item_idx`,
+    /// given the text lengths of every spine item. The fraction read goes by characters, while
+    /// the words left are what the time estimate goes by.
+    pub fn new(lengths: &[TextLength], item_idx: usize, within_item: f64) -> Progress {
+        let item_idx = item_idx.min(lengths.len().saturating_sub(1));
+        let within_item = within_item.max(0.0).min(1.0);
+        let current = lengths.get(item_idx).cloned().unwrap_or_default();
+        let before = lengths.get(..item_idx).unwrap_or_default();
+        let total_chars: usize = lengths.iter().map(|l| l.chars).sum();
+        let total_words: usize = lengths.iter().map(|l| l.words).sum();
+        let chars_read = before.iter().map(|l| l.chars).sum::<usize>() as f64
+            + current.chars as f64 * within_item;
+        let words_read = before.iter().map(|l| l.words).sum::<usize>() as f64
+            + current.words as f64 * within_item;
+        let fraction = if total_chars == 0 {
+            0.0
+        } else {
+            chars_read / total_chars as f64
+        };
+        Progress {
+            item_idx,
+            item_count: lengths.len(),
+            fraction,
+            words_left: (total_words as f64 - words_read).max(0.0).round() as usize,
+        }
+    }
+
+    pub fn minutes_left(&self, words_per_minute: u32) -> u32 {
+        (self.words_left as f64 / f64::from(words_per_minute.max(1))).ceil() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Result;
+
+    #[test]
+    fn text_length() -> Result<()> {
+        let elem: Element = "<html><head><title>Title</title></head>\
+                             <body><p>Two words</p><p>three <b>more</b> words \u{2014}</p>\
+                             <p>\u{543e}\u{8f29}\u{306f}\u{732b}\u{3067}\u{3042}\u{308b}</p>\
+                             <script>var x;</script></body></html>"
+            .parse()?;
+        let length = TextLength::of(&elem);
+        // "Wagahai wa neko de aru" is seven characters
+        assert_eq!(12, length.words);
+        assert_eq!(30, length.chars);
+        Ok(())
+    }
+
+    #[test]
+    fn progress() {
+        let lengths = [
+            TextLength {
+                chars: 100,
+                words: 20,
+            },
+            TextLength {
+                chars: 300,
+                words: 60,
+            },
+        ];
+        let progress = Progress::new(&lengths, 1, 0.5);
+        assert_eq!(2, progress.item_count);
+        assert!((progress.fraction - 0.625).abs() < 1e-9);
+        assert_eq!(30, progress.words_left);
+        assert_eq!(2, progress.minutes_left(20));
+        let past_the_end = Progress::new(&lengths, 5, 1.0);
+        assert_eq!(1, past_the_end.item_idx);
+        assert!((past_the_end.fraction - 1.0).abs() < 1e-9);
+        assert_eq!(0, past_the_end.words_left);
+    }
+}
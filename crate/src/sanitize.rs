@@ -0,0 +1,283 @@
+use crate::xml::Descend;
+use minidom::{Element, ElementBuilder};
+use std::collections::HashSet;
+use std::iter;
+
+#[rustfmt::skip]
+const ELEMENTS: &[&str] = &[
+    // document
+    "html", "head", "title", "meta", "link", "style", "body",
+    // sections and grouping
+    "address", "article", "aside", "blockquote", "center", "dd", "details", "div", "dl", "dt",
+    "figcaption", "figure", "footer", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hgroup", "hr",
+    "li", "main", "nav", "ol", "p", "pre", "section", "summary", "ul",
+    // text-level
+    "a", "abbr", "b", "bdi", "bdo", "big", "br", "cite", "code", "del", "dfn", "em", "font", "i",
+    "ins", "kbd", "mark", "q", "rb", "rp", "rt", "rtc", "ruby", "s", "samp", "small", "span",
+    "strike", "strong", "sub", "sup", "time", "tt", "u", "var", "wbr",
+    // tables
+    "caption", "col", "colgroup", "table", "tbody", "td", "tfoot", "th", "thead", "tr",
+    // embedded content
+    "audio", "img", "picture", "source", "track", "video",
+    // SVG
+    "circle", "defs", "desc", "ellipse", "g", "image", "line", "lineargradient", "path",
+    "polygon", "polyline", "radialgradient", "rect", "stop", "svg", "symbol", "text", "tspan",
+    "use",
+    // MathML
+    "annotation", "math", "menclose", "merror", "mfenced", "mfrac", "mi", "mmultiscripts", "mn",
+    "mo", "mover", "mpadded", "mphantom", "mroot", "mrow", "ms", "mspace", "msqrt", "mstyle",
+    "msub", "msubsup", "msup", "mtable", "mtd", "mtext", "mtr", "munder", "munderover",
+    "semantics",
+];
+
+#[rustfmt::skip]
+const ATTRIBUTES: &[&str] = &[
+    // global
+    "class", "dir", "epub:type", "hidden", "id", "lang", "role", "style", "title", "xml:lang",
+    "xml:space", "aria-describedby", "aria-hidden", "aria-label", "aria-labelledby",
+    // links and embedded content
+    "alt", "charset", "content", "controls", "height", "href", "media", "name", "poster", "rel",
+    "src", "type", "width", "xlink:href",
+    // text-level and lists
+    "cite", "datetime", "reversed", "start", "value",
+    // tables
+    "abbr", "align", "bgcolor", "border", "cellpadding", "cellspacing", "colspan", "headers",
+    "rowspan", "scope", "span", "summary", "valign",
+    // presentational SVG and MathML
+    "cx", "cy", "d", "display", "fill", "fill-opacity", "font-family", "font-size", "mathvariant",
+    "offset", "opacity", "points", "preserveaspectratio", "r", "rx", "ry", "stop-color", "stroke",
+    "stroke-width", "text-anchor", "transform", "version", "viewbox", "x", "x1", "x2", "y", "y1",
+    "y2",
+];
+
+/// Namespaces of the attribute prefixes in `ATTRIBUTES`, other than `xml:`, which is always bound.
+const ATTRIBUTE_PREFIXES: &[(&str, &str)] = &[
+    ("epub", "http://www.idpf.org/2007/ops"),
+    ("xlink", "http://www.w3.org/1999/xlink"),
+];
+const URL_ATTRIBUTES: &[&str] = &["cite", "href", "poster", "src", "xlink:href"];
+const URL_SCHEMES: &[&str] = &["data", "http", "https", "mailto"];
+
+/// Elements whose content goes away with them instead of being unwrapped.
+const DROPPED_ELEMENTS: &[&str] = &[
+    "applet", "base", "embed", "frame", "frameset", "iframe", "noscript", "object", "script",
+    "template",
+];
+
+/// Strips anything that could run code in the reader's origin from chapter markup, using
+/// allow-lists of element names, attribute names and URL schemes.
+///
+/// Disallowed elements are unwrapped, keeping their content, except for the likes of `script`
+/// and `iframe`, which are removed altogether. Event handler attributes are never in the
+/// default allow-list.
+#[derive(Clone, Debug)]
+pub struct Sanitizer {
+    elements: HashSet<String>,
+    attributes: HashSet<String>,
+    url_schemes: HashSet<String>,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Sanitizer {
+        Sanitizer {
+            elements: to_set(ELEMENTS),
+            attributes: to_set(ATTRIBUTES),
+            url_schemes: to_set(URL_SCHEMES),
+        }
+    }
+}
+
+impl Sanitizer {
+    /// Returns a sanitized copy of `root`. The root element itself is always kept.
+    pub fn sanitize(&self, root: &Element) -> Element {
+        let mut clean = self.clean_element(root, None);
+        // minidom doesn't keep the `xmlns:` declarations of the original, so declare the
+        // prefixes the allowed attributes still use
+        for &(prefix, ns) in ATTRIBUTE_PREFIXES {
+            let attr_prefix = format!("{}:", prefix);
+            let in_use = iter::once(&clean)
+                .chain(clean.descendants())
+                .any(|e| e.attrs().any(|(key, _)| key.starts_with(&attr_prefix)));
+            if in_use {
+                clean.set_attr(format!("xmlns:{}", prefix), ns);
+            }
+        }
+        clean
+    }
+
+    #[allow(dead_code)]
+    pub fn allow_element(&mut self, name: &str) -> &mut Sanitizer {
+        self.elements.insert(name.to_lowercase());
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn deny_element(&mut self, name: &str) -> &mut Sanitizer {
+        self.elements.remove(&name.to_lowercase());
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn allow_attribute(&mut self, name: &str) -> &mut Sanitizer {
+        self.attributes.insert(name.to_lowercase());
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn deny_attribute(&mut self, name: &str) -> &mut Sanitizer {
+        self.attributes.remove(&name.to_lowercase());
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn allow_url_scheme(&mut self, scheme: &str) -> &mut Sanitizer {
+        self.url_schemes.insert(scheme.to_lowercase());
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn deny_url_scheme(&mut self, scheme: &str) -> &mut Sanitizer {
+        self.url_schemes.remove(&scheme.to_lowercase());
+        self
+    }
+
+    fn clean_element(&self, elem: &Element, parent_ns: Option<&str>) -> Element {
+        let ns = elem.ns();
+        let mut builder = Element::builder(elem.name());
+        if let Some(ns) = ns.as_ref().filter(|ns| Some(ns.as_str()) != parent_ns) {
+            builder = builder.ns(ns.as_str());
+        }
+        for (key, value) in elem.attrs() {
+            if self.allows_attribute(elem.name(), key, value) {
+                builder = builder.attr(key, value);
+            }
+        }
+        let ns_ref = match ns {
+            Some(ref ns) => Some(ns.as_str()),
+            None => parent_ns,
+        };
+        self.clean_children(elem, ns_ref, builder).build()
+    }
+
+    fn clean_children(
+        &self,
+        elem: &Element,
+        ns: Option<&str>,
+        mut builder: ElementBuilder,
+    ) -> ElementBuilder {
+        for node in elem.nodes() {
+            if let Some(text) = node.as_text() {
+                builder = builder.append(text.to_string());
+            } else if let Some(child) = node.as_element() {
+                let name = child.name().to_lowercase();
+                if self.elements.contains(&name) {
+                    builder = builder.append(self.clean_element(child, ns));
+                } else if !DROPPED_ELEMENTS.contains(&name.as_str()) {
+                    builder = self.clean_children(child, ns, builder);
+                }
+            }
+        }
+        builder
+    }
+
+    fn allows_attribute(&self, elem_name: &str, key: &str, value: &str) -> bool {
+        let key = key.to_lowercase();
+        if !self.attributes.contains(&key) {
+            return false;
+        }
+        if !URL_ATTRIBUTES.contains(&key.as_str()) {
+            return true;
+        }
+        match url_scheme(value) {
+            // `data:` links would open documents the sanitizer never saw
+            Some(ref scheme) if scheme == "data" && elem_name == "a" => false,
+            Some(scheme) => self.url_schemes.contains(&scheme),
+            None => true,
+        }
+    }
+}
+
+fn to_set(list: &[&str]) -> HashSet<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+/// Extracts the scheme of an absolute URL, ignoring the whitespace and control characters
+/// browsers skip over when they parse one (e.g. `java\tscript:`).
+fn url_scheme(url: &str) -> Option<String> {
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect();
+    let colon_idx = url.find(':')?;
+    let scheme = &url[..colon_idx];
+    if scheme.contains(&['/', '?', '#'][..]) {
+        return None;
+    }
+    Some(scheme.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Result;
+
+    fn sanitize(markup: &str) -> Result<String> {
+        let elem: Element = markup.parse()?;
+        let mut bytes = vec![];
+        Sanitizer::default().sanitize(&elem).write_to(&mut bytes)?;
+        Ok(String::from_utf8(bytes)?)
+    }
+
+    #[test]
+    fn removes_scripts_and_frames() -> Result<()> {
+        let html = sanitize("<div><script>alert(1)</script><iframe src='x'>no</iframe>ok</div>")?;
+        assert!(html.ends_with("<div>ok</div>"));
+        Ok(())
+    }
+
+    #[test]
+    fn unwraps_unknown_elements() -> Result<()> {
+        let html = sanitize("<div><form><b>kept</b></form></div>")?;
+        assert!(html.ends_with("<div><b>kept</b></div>"));
+        Ok(())
+    }
+
+    #[test]
+    fn removes_event_handlers_and_script_urls() -> Result<()> {
+        let html = sanitize(
+            "<p onclick='x()'><a href=' java\tscript:x()' id='a'>a</a>\
+             <a href='data:text/html,x'>b</a><img src='data:image/png;base64,' onerror='x()'/>\
+             <a href='other.xhtml#note'>c</a></p>",
+        )?;
+        assert!(html.ends_with(
+            "<p><a id=\"a\">a</a><a>b</a><img src=\"data:image/png;base64,\"/>\
+             <a href=\"other.xhtml#note\">c</a></p>"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn configurable_allow_lists() -> Result<()> {
+        let elem: Element = "<div><iframe src='x'/><em>e</em></div>".parse()?;
+        let mut sanitizer = Sanitizer::default();
+        sanitizer.allow_element("iframe").deny_element("em");
+        let sanitized = sanitizer.sanitize(&elem);
+        let names: Vec<_> = sanitized.children().map(|c| c.name()).collect();
+        assert_eq!(vec!["iframe"], names);
+        assert_eq!("e", sanitized.text());
+        Ok(())
+    }
+
+    #[test]
+    fn declares_attribute_prefixes() -> Result<()> {
+        let html = sanitize(
+            "<html xmlns='http://www.w3.org/1999/xhtml' xmlns:epub='http://www.idpf.org/2007/ops' \
+             xmlns:xlink='http://www.w3.org/1999/xlink'><body><aside epub:type='footnote'>n\
+             </aside></body></html>",
+        )?;
+        let root = "<html xmlns=\"http://www.w3.org/1999/xhtml\" \
+                    xmlns:epub=\"http://www.idpf.org/2007/ops\">";
+        assert!(html.contains(root));
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use std::io::{Cursor, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+pub const CONTAINER_XML: &str = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#;
+
+pub const XHTML: &str = "application/xhtml+xml";
+
+pub const TOC_NCX: &str = r#"<?xml version="1.0"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+  <navMap>
+    <navPoint id="np-1" playOrder="1">
+      <navLabel><text>Chapter 1</text></navLabel>
+      <content src="chapter1.xhtml"/>
+    </navPoint>
+  </navMap>
+</ncx>"#;
+
+/// Builds an EPUB archive in memory: a stored `mimetype` entry followed by the given files.
+pub fn build_epub(files: &[(&str, &str)]) -> Vec<u8> {
+    let files: Vec<(&str, &[u8])> = files.iter().map(|(n, c)| (*n, c.as_bytes())).collect();
+    build_epub_bytes(&files)
+}
+
+/// Like `build_epub`, for files that aren't text.
+pub fn build_epub_bytes(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    zip.start_file("mimetype", stored).unwrap();
+    zip.write_all(b"application/epub+zip").unwrap();
+    for (name, contents) in files {
+        zip.start_file(*name, FileOptions::default()).unwrap();
+        zip.write_all(contents).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+/// Builds a plain zip archive with the given files, for formats other than EPUB.
+pub fn build_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    for (name, contents) in files {
+        zip.start_file(*name, FileOptions::default()).unwrap();
+        zip.write_all(contents).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+/// Writes package documents for test books. The manifest starts with the NCX at `toc.ncx`.
+pub struct OpfBuilder {
+    version: String,
+    metadata: String,
+    manifest: String,
+    spine_attrs: String,
+    spine: String,
+    guide: String,
+}
+
+impl OpfBuilder {
+    pub fn new(version: &str) -> OpfBuilder {
+        let mut builder = OpfBuilder {
+            version: version.to_string(),
+            metadata: String::new(),
+            manifest: String::new(),
+            spine_attrs: String::new(),
+            spine: String::new(),
+            guide: String::new(),
+        };
+        builder.item("ncx", "toc.ncx", "application/x-dtbncx+xml", "");
+        builder
+    }
+
+    /// Adds markup to the metadata, where the `dc` prefix is declared.
+    pub fn metadata(&mut self, markup: &str) -> &mut OpfBuilder {
+        self.metadata.push_str(markup);
+        self
+    }
+
+    /// Adds a manifest item. `attrs` holds any other attributes, e.g. its `properties`.
+    pub fn item(&mut self, id: &str, href: &str, media_type: &str, attrs: &str) -> &mut OpfBuilder {
+        self.manifest.push_str(&format!(
+            "<item id=\"{}\" href=\"{}\" media-type=\"{}\" {}/>",
+            id, href, media_type, attrs
+        ));
+        self
+    }
+
+    /// Adds a spine itemref. `attrs` holds any other attributes, e.g. `linear="no"`.
+    pub fn itemref(&mut self, idref: &str, attrs: &str) -> &mut OpfBuilder {
+        self.spine
+            .push_str(&format!("<itemref idref=\"{}\" {}/>", idref, attrs));
+        self
+    }
+
+    /// Adds an XHTML document to the manifest and to the end of the spine.
+    pub fn chapter(&mut self, id: &str, href: &str) -> &mut OpfBuilder {
+        self.item(id, href, XHTML, "").itemref(id, "")
+    }
+
+    /// Sets the attributes of the spine other than `toc`, e.g. `page-progression-direction`.
+    pub fn spine_attrs(&mut self, attrs: &str) -> &mut OpfBuilder {
+        self.spine_attrs = attrs.to_string();
+        self
+    }
+
+    /// Adds a reference to the EPUB 2 guide.
+    pub fn reference(&mut self, kind: &str, title: &str, href: &str) -> &mut OpfBuilder {
+        self.guide.push_str(&format!(
+            "<reference type=\"{}\" title=\"{}\" href=\"{}\"/>",
+            kind, title, href
+        ));
+        self
+    }
+
+    pub fn build(&self) -> String {
+        let guide = if self.guide.is_empty() {
+            String::new()
+        } else {
+            format!("<guide>{}</guide>", self.guide)
+        };
+        format!(
+            "<?xml version=\"1.0\"?>\n\
+             <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"{}\" \
+             unique-identifier=\"uid\">\
+             <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">{}</metadata>\
+             <manifest>{}</manifest><spine toc=\"ncx\" {}>{}</spine>{}</package>",
+            self.version, self.metadata, self.manifest, self.spine_attrs, self.spine, guide
+        )
+    }
+}
+
+/// Builds an EPUB around the package document `opf`, at `OEBPS/content.opf` next to `TOC_NCX`,
+/// with the given files, whose paths are relative to `OEBPS`.
+pub fn build_book(opf: &str, files: &[(&str, &str)]) -> Vec<u8> {
+    let paths: Vec<_> = files
+        .iter()
+        .map(|(path, _)| format!("OEBPS/{}", path))
+        .collect();
+    let mut all_files = vec![
+        ("META-INF/container.xml", CONTAINER_XML),
+        ("OEBPS/content.opf", opf),
+        ("OEBPS/toc.ncx", TOC_NCX),
+    ];
+    for (path, (_, contents)) in paths.iter().zip(files) {
+        all_files.push((path, contents));
+    }
+    build_epub(&all_files)
+}
+
+/// Builds an EPUB with a single-chapter OPF around the given chapter markup.
+pub fn build_single_chapter_epub(chapter: &str) -> Vec<u8> {
+    let opf = OpfBuilder::new("2.0")
+        .metadata(
+            "<dc:identifier id=\"uid\">urn:uuid:12345678-1234-1234-1234-123456789abc\
+             </dc:identifier><dc:title>Test</dc:title><dc:language>en</dc:language>",
+        )
+        .chapter("chapter1", "chapter1.xhtml")
+        .build();
+    build_book(&opf, &[("chapter1.xhtml", chapter)])
+}
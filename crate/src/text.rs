@@ -0,0 +1,299 @@
+//! Plain text and Markdown books, read with a chapter for each of their top-level headings.
+
+use crate::document::{Document, Metadata};
+use crate::encoding;
+use crate::epub::TocItem;
+use crate::error::Result;
+use crate::layout::Direction;
+use crate::markdown;
+use crate::progress::{Progress, TextLength};
+use crate::xml::to_markup;
+use minidom::Element;
+use std::path::Path;
+
+/// The longest a line of plain text can be and still be taken for a heading.
+const HEADING_MAX_LEN: usize = 60;
+/// Words plain text headings commonly start with, which make a line a heading wherever it is.
+const HEADING_WORDS: &[&str] = &["act", "book", "canto", "chapter", "part", "volume"];
+/// What the table of contents links to chapters start with, followed by the chapter number.
+const CHAPTER_LINK_PREFIX: &str = "#chapter-";
+
+/// A run of lines that makes up a chapter.
+struct Chapter<'a> {
+    title: String,
+    lines: &'a [&'a str],
+    /// Whether the first block of `lines` is the chapter's heading.
+    has_heading: bool,
+}
+
+/// A plain text or Markdown book, split into a chapter for each of its top-level headings.
+pub struct TextDocument {
+    title: String,
+    /// The title and rendered `body` of each chapter.
+    chapters: Vec<(String, Element)>,
+    lengths: Vec<TextLength>,
+    current_idx: usize,
+}
+
+impl TextDocument {
+    /// Reads a text file, rendering it as Markdown when it looks like Markdown.
+    pub fn new(bytes: &[u8]) -> TextDocument {
+        let text = encoding::decode(bytes);
+        let lines: Vec<&str> = text.lines().collect();
+        let first_idx = lines.iter().position(|l| !l.trim().is_empty());
+        let is_markdown = markdown::is_markdown(&text);
+        let headings = if is_markdown {
+            markdown_headings(&lines)
+        } else {
+            text_headings(&lines)
+        };
+        // a book's title is its first line, without any Markdown
+        let title = match (headings.first(), first_idx) {
+            (Some((idx, title)), Some(first_idx)) if *idx == first_idx => title.clone(),
+            (_, Some(first_idx)) => lines[first_idx].trim().to_string(),
+            (_, None) => "Untitled".to_string(),
+        };
+        let chapters: Vec<(String, Element)> = split(&lines, headings, &title)
+            .into_iter()
+            .map(|chapter| {
+                let body = if is_markdown {
+                    markdown::render(chapter.lines, Element::builder("body")).build()
+                } else {
+                    render_text(&chapter)
+                };
+                (chapter.title, body)
+            })
+            .collect();
+        let lengths = chapters.iter().map(|(_, b)| TextLength::of(b)).collect();
+        TextDocument {
+            title,
+            chapters,
+            lengths,
+            current_idx: 0,
+        }
+    }
+}
+
+impl Document for TextDocument {
+    /// Text files don't say what language they're in, so the language is left unknown rather
+    /// than guessed.
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            title: Some(self.title.clone()),
+            authors: vec![],
+            language: None,
+        }
+    }
+
+    fn toc(&mut self) -> Result<Vec<TocItem>> {
+        let toc = self
+            .chapters
+            .iter()
+            .enumerate()
+            .map(|(idx, (title, _))| TocItem {
+                text: title.clone(),
+                href: format!("{}{}", CHAPTER_LINK_PREFIX, idx + 1),
+            })
+            .collect();
+        Ok(toc)
+    }
+
+    fn chapter_count(&self) -> usize {
+        self.chapters.len()
+    }
+
+    fn current_idx(&self) -> Result<usize> {
+        Ok(self.current_idx)
+    }
+
+    fn chapter(&mut self, idx: usize) -> Result<String> {
+        let (_, body) = self.chapters.get(idx).ok_or("chapter out of range")?;
+        let markup = to_markup(body)?;
+        self.current_idx = idx;
+        Ok(markup)
+    }
+
+    /// Follows the links in the table of contents, which are the only ones leading to chapters.
+    fn chapter_by_link(&mut self, link: &str) -> Result<String> {
+        let idx = link
+            .trim_start_matches(CHAPTER_LINK_PREFIX)
+            .parse::<usize>()
+            .ok()
+            .and_then(|number| number.checked_sub(1))
+            .ok_or("link to no chapter")?;
+        self.chapter(idx)
+    }
+
+    fn resource(&mut self, _path: &Path) -> Result<Vec<u8>> {
+        Err("text books have no resources".into())
+    }
+
+    fn direction(&self) -> Direction {
+        Direction::Ltr
+    }
+
+    fn progress(&mut self, item_idx: usize, within_item: f64) -> Result<Progress> {
+        Ok(Progress::new(&self.lengths, item_idx, within_item))
+    }
+}
+
+/// The line indices and text of the headings of the highest level in a Markdown document. When
+/// there's only one, usually the book's title, the headings of the next level split the book too.
+fn markdown_headings(lines: &[&str]) -> Vec<(usize, String)> {
+    let headings = markdown::headings(lines);
+    let top_level = headings.iter().map(|h| h.level).min();
+    let top_count = headings
+        .iter()
+        .filter(|h| Some(h.level) == top_level)
+        .count();
+    let next_level = headings
+        .iter()
+        .map(|h| h.level)
+        .filter(|level| Some(*level) > top_level)
+        .min()
+        .filter(|_| top_count == 1);
+    headings
+        .into_iter()
+        .filter(|h| Some(h.level) == top_level || Some(h.level) == next_level)
+        .map(|h| (h.line_idx, h.text))
+        .collect()
+}
+
+/// Guesses which lines of plain text are chapter headings: short lines standing on their own
+/// that either follow two or more blank lines or start with a word like "Chapter".
+fn text_headings(lines: &[&str]) -> Vec<(usize, String)> {
+    let mut headings = vec![];
+    let mut blank_count = 0;
+    for (idx, line) in lines.iter().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            blank_count += 1;
+            continue;
+        }
+        let stands_alone = (idx == 0 || blank_count > 0)
+            && lines
+                .get(idx + 1)
+                .filter(|l| !l.trim().is_empty())
+                .is_none();
+        let first_word = line
+            .split(|c: char| !c.is_alphanumeric())
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        let is_heading = stands_alone
+            && line.chars().count() <= HEADING_MAX_LEN
+            && ((idx > 0 && blank_count >= 2) || HEADING_WORDS.contains(&first_word.as_str()));
+        if is_heading {
+            headings.push((idx, line.to_string()));
+        }
+        blank_count = 0;
+    }
+    headings
+}
+
+/// Splits `lines` into chapters starting at `headings`. Whatever comes before the first heading
+/// becomes a chapter titled `title`, unless it's blank.
+fn split<'a>(
+    lines: &'a [&'a str],
+    headings: Vec<(usize, String)>,
+    title: &str,
+) -> Vec<Chapter<'a>> {
+    let lead_end = headings.first().map_or(lines.len(), |(idx, _)| *idx);
+    let mut chapters = vec![];
+    if headings.is_empty() || lines[..lead_end].iter().any(|l| !l.trim().is_empty()) {
+        chapters.push(Chapter {
+            title: title.to_string(),
+            lines: &lines[..lead_end],
+            has_heading: false,
+        });
+    }
+    let ends: Vec<usize> = headings
+        .iter()
+        .skip(1)
+        .map(|(idx, _)| *idx)
+        .chain(std::iter::once(lines.len()))
+        .collect();
+    for ((start, title), end) in headings.into_iter().zip(ends) {
+        chapters.push(Chapter {
+            title,
+            lines: &lines[start..end],
+            has_heading: true,
+        });
+    }
+    chapters
+}
+
+/// Renders plain text with a paragraph for each block of lines between blank lines.
+fn render_text(chapter: &Chapter) -> Element {
+    let mut builder = Element::builder("body");
+    let blocks = chapter
+        .lines
+        .split(|l| l.trim().is_empty())
+        .filter(|b| !b.is_empty());
+    for (idx, block) in blocks.enumerate() {
+        let name = if chapter.has_heading && idx == 0 {
+            "h1"
+        } else {
+            "p"
+        };
+        let text: Vec<&str> = block.iter().map(|l| l.trim()).collect();
+        builder = builder.append(Element::builder(name).append(text.join("\n")).build());
+    }
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toc_titles(doc: &mut TextDocument) -> Result<Vec<String>> {
+        Ok(doc.toc()?.into_iter().map(|i| i.text).collect())
+    }
+
+    #[test]
+    fn plain_text() -> Result<()> {
+        let text = "The Book of Tea\nby Kakuzo Okakura\n\n\n\nI. The Cup of Humanity\n\n\
+                    Tea began as a medicine\nand grew into a beverage.\n\n\
+                    A short line\n\nCHAPTER II. The Schools of Tea\n\nTea is a work of art.\n";
+        let mut doc = TextDocument::new(text.as_bytes());
+        assert_eq!(
+            vec![
+                "The Book of Tea",
+                "I. The Cup of Humanity",
+                "CHAPTER II. The Schools of Tea"
+            ],
+            toc_titles(&mut doc)?
+        );
+        let chapter = doc.chapter(1)?;
+        assert!(chapter.contains("<h1>I. The Cup of Humanity</h1>"));
+        assert!(chapter.contains("<p>Tea began as a medicine\nand grew into a beverage.</p>"));
+        assert!(chapter.contains("<p>A short line</p>"));
+        let link = doc.toc()?.pop().ok_or("no toc")?.href;
+        assert!(doc
+            .chapter_by_link(&link)?
+            .contains("Tea is a work of art."));
+        assert_eq!(2, doc.current_idx()?);
+        assert!(doc.chapter_by_link("tea.txt").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn markdown() -> Result<()> {
+        let text = "# Walden\n\nIntro.\n\n## Economy\n\nWhen I *wrote*...\n\n\
+                    ## Reading\n\n```\n## not a chapter\n```\n";
+        let mut doc = TextDocument::new(text.as_bytes());
+        assert_eq!(vec!["Walden", "Economy", "Reading"], toc_titles(&mut doc)?);
+        assert!(doc.chapter(0)?.contains("Intro."));
+        assert!(!doc.chapter(1)?.contains("Intro."));
+        let text = "Preface.\n\n## Economy\n\nWhen I *wrote*...\n\n## Reading\n";
+        let mut doc = TextDocument::new(text.as_bytes());
+        assert_eq!(
+            vec!["Preface.", "Economy", "Reading"],
+            toc_titles(&mut doc)?
+        );
+        let chapter = doc.chapter(1)?;
+        assert!(chapter.contains("<h2>Economy</h2>"));
+        assert!(chapter.contains("<em>wrote</em>"));
+        Ok(())
+    }
+}
@@ -0,0 +1,552 @@
+//! A lightweight EPUB checker, for finding the problems in a book that reading systems tend to
+//! work around or silently give up on.
+
+use crate::encoding;
+use crate::epub::{resolve_path, split_href};
+use crate::error;
+use crate::html::parse_html;
+use crate::xml::{parse_xml, Descend};
+use minidom::Element;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use url::percent_encoding::percent_decode;
+use url::Url;
+use zip::{CompressionMethod, ZipArchive};
+
+type Zip<'a> = ZipArchive<Cursor<&'a [u8]>>;
+
+const MIMETYPE: &str = "application/epub+zip";
+const PACKAGE_MEDIA_TYPE: &str = "application/oebps-package+xml";
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+const REQUIRED_METADATA: &[&str] = &["identifier", "title", "language"];
+const LINKED_DOC_TYPES: &[&str] = &["application/xhtml+xml", "text/html"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Path of the file in the container the problem is in, empty for the container itself.
+    pub location: String,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error<L: AsRef<Path>>(location: L, message: String) -> Diagnostic {
+        Diagnostic::new(Severity::Error, location, message)
+    }
+
+    fn warning<L: AsRef<Path>>(location: L, message: String) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, location, message)
+    }
+
+    fn new<L: AsRef<Path>>(severity: Severity, location: L, message: String) -> Diagnostic {
+        let location = location.as_ref().to_string_lossy().into_owned();
+        Diagnostic {
+            severity,
+            location,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        if self.location.is_empty() {
+            write!(fmt, "{}: {}", severity, self.message)
+        } else {
+            write!(fmt, "{}: {}: {}", severity, self.location, self.message)
+        }
+    }
+}
+
+/// Checks the structure of the EPUB in `bytes`, stopping at the first problem that makes the
+/// rest of the checks impossible, e.g. a missing package document.
+pub fn validate(bytes: &[u8]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut zip = match ZipArchive::new(Cursor::new(bytes)) {
+        Ok(zip) => zip,
+        Err(e) => {
+            diagnostics.push(Diagnostic::error("", format!("not a zip archive: {}", e)));
+            return diagnostics;
+        }
+    };
+    let files: HashSet<PathBuf> = (0..zip.len())
+        .filter_map(|i| zip.by_index(i).ok().map(|f| PathBuf::from(f.name())))
+        .collect();
+    check_mimetype(&mut zip, &mut diagnostics);
+    if let Some(opf_path) = check_container(&mut zip, &files, &mut diagnostics) {
+        check_package(&mut zip, &files, &opf_path, &mut diagnostics);
+    }
+    diagnostics
+}
+
+fn check_mimetype(zip: &mut Zip, diagnostics: &mut Vec<Diagnostic>) {
+    let mut first_file = match zip.by_index(0) {
+        Ok(file) => file,
+        Err(_) => {
+            diagnostics.push(Diagnostic::error("", "the archive is empty".to_string()));
+            return;
+        }
+    };
+    if first_file.name() != "mimetype" {
+        let message = "mimetype must be the first file in the archive".to_string();
+        diagnostics.push(Diagnostic::error("mimetype", message));
+        return;
+    }
+    if first_file.compression() != CompressionMethod::Stored {
+        let message = "mimetype must be stored uncompressed".to_string();
+        diagnostics.push(Diagnostic::error("mimetype", message));
+    }
+    let mut mimetype = String::new();
+    if first_file.read_to_string(&mut mimetype).is_err() || mimetype != MIMETYPE {
+        let message = format!("mimetype must contain exactly {}", MIMETYPE);
+        diagnostics.push(Diagnostic::error("mimetype", message));
+    }
+}
+
+/// Returns the path of the package document, if container.xml points at one that exists.
+fn check_container(
+    zip: &mut Zip,
+    files: &HashSet<PathBuf>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<PathBuf> {
+    let container_doc = match read_doc(zip, Path::new(CONTAINER_PATH), |b| parse_xml(b)) {
+        Ok(doc) => doc,
+        Err(message) => {
+            diagnostics.push(Diagnostic::error(CONTAINER_PATH, message));
+            return None;
+        }
+    };
+    let mut opf_path = None;
+    for rootfile in container_doc
+        .descendants()
+        .filter(|e| e.name() == "rootfile")
+    {
+        let full_path = match rootfile.attr("full-path") {
+            Some(full_path) => PathBuf::from(full_path),
+            None => {
+                let message = "rootfile without a full-path".to_string();
+                diagnostics.push(Diagnostic::error(CONTAINER_PATH, message));
+                continue;
+            }
+        };
+        if rootfile.attr("media-type") != Some(PACKAGE_MEDIA_TYPE) {
+            let message = format!("rootfile media-type should be {}", PACKAGE_MEDIA_TYPE);
+            diagnostics.push(Diagnostic::warning(CONTAINER_PATH, message));
+        }
+        if !files.contains(&full_path) {
+            let message = format!("rootfile {} not found", full_path.display());
+            diagnostics.push(Diagnostic::error(CONTAINER_PATH, message));
+        } else if opf_path.is_none() {
+            opf_path = Some(full_path);
+        }
+    }
+    if opf_path.is_none() {
+        let message = "no package document to read".to_string();
+        diagnostics.push(Diagnostic::error(CONTAINER_PATH, message));
+    }
+    opf_path
+}
+
+fn check_package(
+    zip: &mut Zip,
+    files: &HashSet<PathBuf>,
+    opf_path: &Path,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let opf_doc = match read_doc(zip, opf_path, |b| parse_xml(b)) {
+        Ok(doc) => doc,
+        Err(message) => {
+            diagnostics.push(Diagnostic::error(opf_path, message));
+            return;
+        }
+    };
+    check_metadata(&opf_doc, opf_path, diagnostics);
+    let manifest = check_manifest(&opf_doc, files, opf_path, diagnostics);
+    check_spine(&opf_doc, &manifest, opf_path, diagnostics);
+    let doc_paths: Vec<&PathBuf> = manifest
+        .values()
+        .filter(|(path, media_type)| {
+            LINKED_DOC_TYPES.contains(&media_type.as_str()) && files.contains(path)
+        })
+        .map(|(path, _)| path)
+        .collect();
+    check_documents(zip, files, &doc_paths, diagnostics);
+}
+
+fn check_metadata(opf_doc: &Element, opf_path: &Path, diagnostics: &mut Vec<Diagnostic>) {
+    let metadata: Vec<&Element> = opf_doc
+        .children()
+        .filter(|n| n.name() == "metadata")
+        .flat_map(|n| n.children())
+        .collect();
+    for name in REQUIRED_METADATA {
+        let present = metadata
+            .iter()
+            .any(|m| m.name() == *name && !m.text().trim().is_empty());
+        if !present {
+            let message = format!("required metadata dc:{} missing", name);
+            diagnostics.push(Diagnostic::error(opf_path, message));
+        }
+    }
+    match opf_doc.attr("unique-identifier") {
+        Some(id) => {
+            let matches = metadata
+                .iter()
+                .any(|m| m.name() == "identifier" && m.attr("id") == Some(id));
+            if !matches {
+                let message = format!("unique-identifier {} matches no dc:identifier", id);
+                diagnostics.push(Diagnostic::error(opf_path, message));
+            }
+        }
+        None => {
+            let message = "package has no unique-identifier".to_string();
+            diagnostics.push(Diagnostic::error(opf_path, message));
+        }
+    }
+    let is_epub3 = opf_doc.attr("version").unwrap_or_default().starts_with('3');
+    let has_modified = metadata
+        .iter()
+        .any(|m| m.name() == "meta" && m.attr("property") == Some("dcterms:modified"));
+    if is_epub3 && !has_modified {
+        let message = "required metadata dcterms:modified missing".to_string();
+        diagnostics.push(Diagnostic::error(opf_path, message));
+    }
+}
+
+/// Returns the path and media type of every local manifest item, by id.
+fn check_manifest(
+    opf_doc: &Element,
+    files: &HashSet<PathBuf>,
+    opf_path: &Path,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> HashMap<String, (PathBuf, String)> {
+    let mut manifest = HashMap::new();
+    let mut remote_ids = HashSet::new();
+    let items = opf_doc
+        .children()
+        .filter(|n| n.name() == "manifest")
+        .flat_map(|n| n.children())
+        .filter(|c| c.name() == "item");
+    for item in items {
+        let (id, href, media_type) =
+            match (item.attr("id"), item.attr("href"), item.attr("media-type")) {
+                (Some(id), Some(href), Some(media_type)) => (id, href, media_type),
+                _ => {
+                    let message = "manifest item needs an id, href and media-type".to_string();
+                    diagnostics.push(Diagnostic::error(opf_path, message));
+                    continue;
+                }
+            };
+        if manifest.contains_key(id) || remote_ids.contains(id) {
+            let message = format!("duplicate manifest id {}", id);
+            diagnostics.push(Diagnostic::error(opf_path, message));
+            continue;
+        }
+        if Url::parse(href).is_ok() {
+            remote_ids.insert(id);
+            continue;
+        }
+        let path = resolve_path(&decode(href), opf_path);
+        if !files.contains(&path) {
+            let message = format!("manifest item {} ({}) not found", id, href);
+            diagnostics.push(Diagnostic::error(opf_path, message));
+        }
+        manifest.insert(id.to_string(), (path, media_type.to_string()));
+    }
+    let listed: HashSet<&PathBuf> = manifest.values().map(|(path, _)| path).collect();
+    let mut unlisted: Vec<&PathBuf> = files
+        .iter()
+        .filter(|f| {
+            !f.to_string_lossy().ends_with('/')
+                && !f.starts_with("META-INF")
+                && *f != Path::new("mimetype")
+                && *f != opf_path
+                && !listed.contains(f)
+        })
+        .collect();
+    unlisted.sort();
+    for file in unlisted {
+        let message = "file not listed in the manifest".to_string();
+        diagnostics.push(Diagnostic::warning(file, message));
+    }
+    for id in remote_ids {
+        manifest.insert(id.to_string(), (PathBuf::new(), String::new()));
+    }
+    manifest
+}
+
+fn check_spine(
+    opf_doc: &Element,
+    manifest: &HashMap<String, (PathBuf, String)>,
+    opf_path: &Path,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let spine = match opf_doc.children().find(|n| n.name() == "spine") {
+        Some(spine) => spine,
+        None => {
+            let message = "spine element missing".to_string();
+            diagnostics.push(Diagnostic::error(opf_path, message));
+            return;
+        }
+    };
+    if let Some(toc) = spine.attr("toc") {
+        if !manifest.contains_key(toc) {
+            let message = format!("spine toc {} not in manifest", toc);
+            diagnostics.push(Diagnostic::error(opf_path, message));
+        }
+    }
+    let mut itemref_count = 0;
+    for itemref in spine.children().filter(|c| c.name() == "itemref") {
+        itemref_count += 1;
+        match itemref.attr("idref") {
+            Some(idref) if !manifest.contains_key(idref) => {
+                let message = format!("spine itemref {} not in manifest", idref);
+                diagnostics.push(Diagnostic::error(opf_path, message));
+            }
+            Some(_) => (),
+            None => {
+                let message = "spine itemref without an idref".to_string();
+                diagnostics.push(Diagnostic::error(opf_path, message));
+            }
+        }
+    }
+    if itemref_count == 0 {
+        let message = "spine is empty".to_string();
+        diagnostics.push(Diagnostic::error(opf_path, message));
+    }
+}
+
+/// Looks for duplicate ids in content documents and links between them that lead nowhere.
+fn check_documents(
+    zip: &mut Zip,
+    files: &HashSet<PathBuf>,
+    doc_paths: &[&PathBuf],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut docs = vec![];
+    let mut ids: HashMap<&Path, HashSet<&str>> = HashMap::new();
+    for path in doc_paths {
+        let bytes = match read_file(zip, path) {
+            Ok(bytes) => bytes,
+            Err(message) => {
+                diagnostics.push(Diagnostic::error(path, message));
+                continue;
+            }
+        };
+        if let Some(label) = encoding::unsupported_label(&bytes) {
+            let message = format!("unsupported character encoding {}, read as UTF-8", label);
+            diagnostics.push(Diagnostic::warning(path, message));
+        }
+        let xml_error = match parse_xml(&bytes[..]) {
+            Ok(doc) => {
+                docs.push((path.as_path(), doc));
+                continue;
+            }
+            Err(e) => e,
+        };
+        let message = format!("not well-formed: {}", xml_error);
+        diagnostics.push(Diagnostic::error(path, message));
+        // the reader still opens it as HTML, so its ids and links are checked all the same
+        match parse_html(&bytes[..]) {
+            Ok(doc) => docs.push((path.as_path(), doc)),
+            Err(e) => {
+                let message = format!("couldn't be parsed: {}", e);
+                diagnostics.push(Diagnostic::error(path, message));
+            }
+        }
+    }
+    docs.sort_by_key(|(path, _)| *path);
+    for (path, doc) in &docs {
+        let doc_ids = ids.entry(*path).or_default();
+        for id in doc.descendants().filter_map(|e| e.attr("id")) {
+            if !doc_ids.insert(id) {
+                let message = format!("duplicate id {}", id);
+                diagnostics.push(Diagnostic::error(path, message));
+            }
+        }
+    }
+    for (path, doc) in &docs {
+        let hrefs = doc
+            .descendants()
+            .filter(|e| e.name() == "a")
+            .filter_map(|e| e.attr("href"));
+        for href in hrefs {
+            if Url::parse(href).is_ok() {
+                continue;
+            }
+            let (target, fragment) = match split_href(href, path) {
+                Ok((target, fragment)) => {
+                    (PathBuf::from(decode(&target.to_string_lossy())), fragment)
+                }
+                Err(_) => {
+                    let message = format!("invalid link {}", href);
+                    diagnostics.push(Diagnostic::error(path, message));
+                    continue;
+                }
+            };
+            if !files.contains(&target) {
+                let message = format!("broken link {}", href);
+                diagnostics.push(Diagnostic::error(path, message));
+                continue;
+            }
+            let fragment = fragment.map(|f| decode(&f));
+            if let (Some(fragment), Some(target_ids)) = (fragment, ids.get(target.as_path())) {
+                if !target_ids.contains(fragment.as_str()) {
+                    let message = format!("link to missing fragment {}", href);
+                    diagnostics.push(Diagnostic::error(path, message));
+                }
+            }
+        }
+    }
+}
+
+fn read_doc<F>(zip: &mut Zip, path: &Path, parse: F) -> Result<Element, String>
+where
+    F: Fn(&[u8]) -> error::Result<Element>,
+{
+    let bytes = read_file(zip, path)?;
+    parse(&bytes).map_err(|e| format!("couldn't be parsed: {}", e))
+}
+
+fn read_file(zip: &mut Zip, path: &Path) -> Result<Vec<u8>, String> {
+    let path_str = path.to_str().ok_or("invalid path")?;
+    let mut bytes = vec![];
+    let mut file = zip
+        .by_name(path_str)
+        .map_err(|_| "file not found".to_string())?;
+    file.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+fn decode(href: &str) -> String {
+    percent_decode(href.as_bytes())
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{self, Format};
+    use crate::test_utils::{self, OpfBuilder, XHTML};
+    use std::io::Write;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    fn has(diagnostics: &[Diagnostic], severity: Severity, location: &str, message: &str) -> bool {
+        diagnostics
+            .iter()
+            .any(|d| d.severity == severity && d.location == location && d.message == message)
+    }
+
+    #[test]
+    fn valid_epub() {
+        let chapter = "<html><body><p id='a'>Text</p><a href='#a'>a</a></body></html>";
+        let diagnostics = validate(&test_utils::build_single_chapter_epub(chapter));
+        assert_eq!(Vec::<Diagnostic>::new(), diagnostics);
+    }
+
+    #[test]
+    fn unsupported_encoding() {
+        let chapter = "<?xml version='1.0' encoding='Shift_JIS'?><html><body>Text</body></html>";
+        let diagnostics = validate(&test_utils::build_single_chapter_epub(chapter));
+        let message = "unsupported character encoding shift_jis, read as UTF-8";
+        let location = "OEBPS/chapter1.xhtml";
+        assert_eq!(
+            vec![Diagnostic::warning(location, message.to_string())],
+            diagnostics
+        );
+    }
+
+    #[test]
+    fn malformed_document() {
+        let chapter = "<html><body><p id='a'>Text<br></p><a href='#b'>b</a></body></html>";
+        let diagnostics = validate(&test_utils::build_single_chapter_epub(chapter));
+        let location = "OEBPS/chapter1.xhtml";
+        assert_eq!(2, diagnostics.len());
+        assert_eq!(Severity::Error, diagnostics[0].severity);
+        assert_eq!(location, diagnostics[0].location);
+        assert!(diagnostics[0].message.starts_with("not well-formed: "));
+        let message = "link to missing fragment #b";
+        assert!(has(&diagnostics, Severity::Error, location, message));
+    }
+
+    #[test]
+    fn mimetype() {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        zip.start_file(CONTAINER_PATH, FileOptions::default())
+            .unwrap();
+        zip.write_all(test_utils::CONTAINER_XML.as_bytes()).unwrap();
+        zip.start_file("mimetype", FileOptions::default()).unwrap();
+        zip.write_all(MIMETYPE.as_bytes()).unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+        let diagnostics = validate(&bytes);
+        let message = "mimetype must be the first file in the archive";
+        assert!(has(&diagnostics, Severity::Error, "mimetype", message));
+        let message = "rootfile OEBPS/content.opf not found";
+        assert!(has(&diagnostics, Severity::Error, CONTAINER_PATH, message));
+        assert_eq!(1, validate(b"not a zip").len());
+        // opened as a broken EPUB, since it has a container.xml
+        let bytes = test_utils::build_zip(&[
+            ("mimetype", b"application/epub"),
+            (CONTAINER_PATH, test_utils::CONTAINER_XML.as_bytes()),
+        ]);
+        assert_eq!(Format::BrokenEpub, format::detect(&bytes));
+        let diagnostics = validate(&bytes);
+        let message = format!("mimetype must contain exactly {}", MIMETYPE);
+        assert!(has(&diagnostics, Severity::Error, "mimetype", &message));
+    }
+
+    #[test]
+    fn broken_epub() {
+        let opf = OpfBuilder::new("3.0")
+            .metadata(
+                "<dc:identifier id=\"uid\">urn:uuid:12345678-1234-1234-1234-123456789abc\
+                 </dc:identifier><dc:language>en</dc:language>",
+            )
+            .chapter("c1", "chapter1.xhtml")
+            .item("c2", "chapter2.xhtml", XHTML, "")
+            .itemref("c3", "")
+            .build();
+        let chapter = "<html><body><p id='a'>a</p><p id='a'>b</p>\
+                       <a href='chapter2.xhtml'>c</a><a href='#b'>d</a>\
+                       <a href='https://example.org'>e</a></body></html>";
+        let files = [("chapter1.xhtml", chapter), ("extra.css", "p {}")];
+        let bytes = test_utils::build_book(&opf, &files);
+        let diagnostics = validate(&bytes);
+        let opf_path = "OEBPS/content.opf";
+        let chapter_path = "OEBPS/chapter1.xhtml";
+        let errors = [
+            (opf_path, "required metadata dc:title missing"),
+            (opf_path, "required metadata dcterms:modified missing"),
+            (opf_path, "manifest item c2 (chapter2.xhtml) not found"),
+            (opf_path, "spine itemref c3 not in manifest"),
+            (chapter_path, "duplicate id a"),
+            (chapter_path, "broken link chapter2.xhtml"),
+            (chapter_path, "link to missing fragment #b"),
+        ];
+        for (location, message) in &errors {
+            assert!(has(&diagnostics, Severity::Error, location, message));
+        }
+        let message = "file not listed in the manifest";
+        assert!(has(
+            &diagnostics,
+            Severity::Warning,
+            "OEBPS/extra.css",
+            message
+        ));
+        assert_eq!(errors.len() + 1, diagnostics.len());
+    }
+}
@@ -1,29 +1,53 @@
-use crate::epub::{Epub, TocItem};
+use crate::document;
+use crate::epub::{Rendition, TocItem};
+use crate::error::Error;
+use crate::export;
+use crate::format::{self, Format};
+use crate::history::Location;
+use crate::layout::{Direction, Layout, WritingMode};
 use crate::utils;
+use crate::validate::validate;
 use futures::Future;
-use js_sys::{ArrayBuffer, Promise, Uint8Array};
-use std::cell::RefCell;
+use js_sys::{Array, ArrayBuffer, Promise, Uint8Array};
+use std::cell::{Cell, RefCell};
 use std::cmp::{max, min};
+use std::ops::Range;
 use std::rc::Rc;
-use url::Url;
+use url::percent_encoding::{percent_decode, utf8_percent_encode, DEFAULT_ENCODE_SET};
+use url::{define_encode_set, Url};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::{future_to_promise, JsFuture};
 use web_sys::{
-    Document, Element, Event, EventTarget, FileReader, HtmlElement, HtmlInputElement, Response,
-    ShadowRootInit, ShadowRootMode,
+    console, Blob, BlobPropertyBag, Document, Element, Event, EventTarget, FileReader,
+    HashChangeEvent, HtmlAnchorElement, HtmlElement, HtmlInputElement, HtmlSelectElement,
+    PopStateEvent, Response, ShadowRootInit, ShadowRootMode, TouchEvent, WheelEvent,
 };
 
 const FONT_SIZE_DEFAULT: isize = 20;
 const FONT_SIZE_INCREMENT: isize = 2;
 const FONT_SIZE_MIN: isize = 6;
 const FONT_SIZE_MAX: isize = 60;
-type EpubRef = Rc<RefCell<Option<Epub>>>;
+const SWIPE_MIN_DISTANCE: i32 = 50;
+const WORDS_PER_MINUTE_DEFAULT: u32 = 250;
+/// Sizes comic pages by the `fit-width` or `fit-page` class #content gets from #fit.
+const COMIC_PAGE_STYLE: &str = "<style>\
+    :host(.fit-width) img { display: block; width: 100%; }\
+    :host(.fit-page) img { display: block; height: 100%; margin: 0 auto; max-width: 100%; \
+                           object-fit: contain; }\
+    </style>";
+type DocumentRef = Rc<RefCell<Option<Box<dyn document::Document>>>>;
 type JsResult<T> = std::result::Result<T, JsValue>;
-type EventHandler = Box<FnMut(Event) -> JsResult<()>>;
+type EventHandler = Box<dyn FnMut(Event) -> JsResult<()>>;
 trait OnceEventHandler: FnOnce(Event) -> JsResult<()> + 'static {}
 impl<T: FnOnce(Event) -> JsResult<()> + 'static> OnceEventHandler for T {}
 
+define_encode_set! {
+    /// What's escaped in the locations put in the URL hash, which is read back percent-decoded.
+    pub HASH_ENCODE_SET = [DEFAULT_ENCODE_SET] | {'%'}
+}
+
+#[derive(Clone, Copy)]
 enum Cmp {
     More,
     Less,
@@ -36,18 +60,21 @@ pub fn run() -> JsResult<()> {
 }
 
 struct LeedorApp {
-    epub: EpubRef,
+    document: DocumentRef,
+    touch_start_x: Rc<Cell<Option<i32>>>,
 }
 
 impl LeedorApp {
     pub fn new() -> LeedorApp {
         LeedorApp {
-            epub: Rc::new(RefCell::new(None)),
+            document: Rc::new(RefCell::new(None)),
+            touch_start_x: Rc::new(Cell::new(None)),
         }
     }
 
     pub fn run(&self) -> JsResult<()> {
         utils::set_panic_hook();
+        let window = web_sys::window().ok_or("no window")?;
         let document = document()?;
         let file_input = document.get_element_by_id("file").ok_or("no #file")?;
         let prev_button = document.get_element_by_id("prev").ok_or("no #prev")?;
@@ -57,26 +84,57 @@ impl LeedorApp {
         let toggle_toc = document
             .get_element_by_id("toggle-toc")
             .ok_or("no #toggle-toc")?;
+        let export_button = document.get_element_by_id("export").ok_or("no #export")?;
         let toc = document.get_element_by_id("toc").ok_or("no #toc")?;
         let content = document.get_element_by_id("content").ok_or("no #content")?;
         let shadow_root = content.attach_shadow(&ShadowRootInit::new(ShadowRootMode::Open))?;
         let samples = document.get_element_by_id("samples").ok_or("no #samples")?;
+        let rendition = document
+            .get_element_by_id("rendition")
+            .ok_or("no #rendition")?;
+        let note = document.get_element_by_id("note").ok_or("no #note")?;
+        let note_root = note.attach_shadow(&ShadowRootInit::new(ShadowRootMode::Open))?;
+        let reading_speed = document
+            .get_element_by_id("reading-speed")
+            .ok_or("no #reading-speed")?;
+        let page = document.get_element_by_id("page").ok_or("no #page")?;
+        let fit = document.get_element_by_id("fit").ok_or("no #fit")?;
+        let export_range = document
+            .get_element_by_id("export-range")
+            .ok_or("no #export-range")?;
         add_event_listener(file_input, "change", self.handle_file_change())?;
         add_event_listener(prev_button, "click", self.handle_arrows(Cmp::Less))?;
         add_event_listener(next_button, "click", self.handle_arrows(Cmp::More))?;
         add_event_listener(smaller_button, "click", self.handle_font(Cmp::Less))?;
         add_event_listener(larger_button, "click", self.handle_font(Cmp::More))?;
         add_event_listener(toggle_toc, "click", self.handle_toggle_toc())?;
+        add_event_listener(rendition, "change", self.handle_rendition())?;
+        add_event_listener(export_button, "click", self.handle_export())?;
+        add_event_listener(content.clone(), "touchstart", self.handle_touch_start())?;
+        add_event_listener(content.clone(), "touchend", self.handle_touch_end())?;
+        add_event_listener(content.clone(), "wheel", self.handle_wheel())?;
+        add_event_listener(content, "scroll", self.handle_progress_change())?;
+        add_event_listener(reading_speed, "change", self.handle_progress_change())?;
+        add_event_listener(page, "change", self.handle_go_to_page())?;
+        add_event_listener(fit, "change", self.handle_fit())?;
+        add_event_listener(export_range, "change", self.handle_export_range())?;
         add_event_listener(toc, "click", self.handle_click(true))?;
         add_event_listener(shadow_root, "click", self.handle_click(false))?;
+        add_event_listener(note_root, "click", self.handle_click(false))?;
         add_once_event_listener(samples, "click", self.handle_sample_click())?;
-        Ok(())
+        add_event_listener(window.clone(), "popstate", self.handle_pop_state())?;
+        add_event_listener(window, "hashchange", self.handle_hash_change())?;
+        open_from_hash(self.document.clone())
     }
 
     fn handle_click(&self, is_toc: bool) -> EventHandler {
-        let epub_ref = self.epub.clone();
+        let document_ref = self.document.clone();
         let handler = move |e: Event| -> JsResult<()> {
-            let clicked_elem: Element = e.target().ok_or("no event target")?.dyn_into()?;
+            let note_div = document()?.get_element_by_id("note").ok_or("no #note")?;
+            note_div.class_list().add_1("hidden")?;
+            // the target is retargeted to the page's host in fixed-layout spreads, so look at
+            // where the click really happened
+            let clicked_elem: Element = e.composed_path().shift().dyn_into()?;
             let anchor;
             if clicked_elem.tag_name() == "A" {
                 anchor = clicked_elem;
@@ -95,50 +153,191 @@ impl LeedorApp {
                 return Ok(());
             }
             e.prevent_default();
-            let mut epub_option = epub_ref.borrow_mut();
-            let epub = epub_option.as_mut().ok_or("no epub loaded yet")?;
+            let mut document_option = document_ref.borrow_mut();
+            let doc = document_option
+                .as_mut()
+                .ok_or("no book loaded yet")?
+                .as_mut();
+            if !is_toc {
+                let epub_type = anchor.get_attribute("epub:type").unwrap_or_default();
+                let role = anchor.get_attribute("role").unwrap_or_default();
+                let noteref =
+                    epub_type.split_whitespace().any(|t| t == "noteref") || role == "doc-noteref";
+                if let Some(note) = doc.note(&href, noteref)? {
+                    return render_note(&note);
+                }
+            }
+            let history_position = doc.history_position();
             let content = if is_toc {
-                epub.chapter_by_toc_link(&href)?
+                doc.chapter_by_toc_link(&href)?
             } else {
-                epub.chapter_by_link(&href)?
+                doc.chapter_by_link(&href)?
             };
-            render_content(&content)?;
-            let document = document()?;
-            let toc_nav = document.get_element_by_id("toc-nav").ok_or("no #toc-nav")?;
+            render_chapter(doc, &content)?;
+            // links to where the reader already is, or outside the spine, add no history entry
+            let moved = doc.history_position() != history_position;
+            update_history_state(doc, moved)?;
+            let toc_nav = document()?
+                .get_element_by_id("toc-nav")
+                .ok_or("no #toc-nav")?;
             toc_nav.class_list().add_1("hidden")?;
             let url = utils::parse_relative_url(&href)?;
-            let fragment = match url.fragment() {
-                Some(s) => s,
+            scroll_to_fragment(url.fragment())
+        };
+        Box::new(handler)
+    }
+
+    /// Goes back or forward to a location in the book when the browser's buttons are used.
+    fn handle_pop_state(&self) -> EventHandler {
+        let document_ref = self.document.clone();
+        let handler = move |e: Event| -> JsResult<()> {
+            let pop_state_event: PopStateEvent = e.dyn_into()?;
+            let position = match pop_state_event.state().as_f64() {
+                Some(p) => p as usize,
                 None => return Ok(()),
             };
-            let shadow_root = document
-                .get_element_by_id("content")
-                .ok_or("no #content")?
-                .shadow_root()
-                .ok_or("no shadow root")?;
-            if let Some(elem) = shadow_root.get_element_by_id(fragment) {
-                elem.scroll_into_view();
+            let mut document_option = document_ref.borrow_mut();
+            let doc = match document_option.as_mut() {
+                Some(doc) => doc.as_mut(),
+                None => return Ok(()),
+            };
+            if let Some((content, location)) = doc.go_to_history(position)? {
+                render_chapter(doc, &content)?;
+                scroll_to_fragment(location.fragment.as_ref())?;
             }
             Ok(())
         };
         Box::new(handler)
     }
 
+    /// Follows a location typed or pasted into the URL, opening the sample it's in when it isn't
+    /// the one that's open.
+    fn handle_hash_change(&self) -> EventHandler {
+        let document_ref = self.document.clone();
+        let handler = move |e: Event| -> JsResult<()> {
+            let hash_change_event: HashChangeEvent = e.dyn_into()?;
+            let old_url = Url::parse(&hash_change_event.old_url()).map_err(|e| e.to_string())?;
+            let (old_book, _) = split_hash(old_url.fragment().unwrap_or_default());
+            let window = web_sys::window().ok_or("no window")?;
+            let (book, location) = split_hash(&window.location().hash()?);
+            if book != old_book || document_ref.borrow().is_none() {
+                return open_from_hash(document_ref.clone());
+            }
+            match location {
+                Some(location) => go_to_hash_location(&document_ref, &location),
+                None => Ok(()),
+            }
+        };
+        Box::new(handler)
+    }
+
     fn handle_arrows(&self, cmp: Cmp) -> EventHandler {
-        let epub_ref = self.epub.clone();
-        let handler = move |_| -> JsResult<()> {
-            let mut epub_option = epub_ref.borrow_mut();
-            let epub = epub_option.as_mut().ok_or("no epub loaded yet")?;
-            let content = match cmp {
-                Cmp::Less => epub.prev_chapter()?,
-                Cmp::More => epub.next_chapter()?,
+        let document_ref = self.document.clone();
+        let handler = move |_| -> JsResult<()> { turn_page(&document_ref, cmp) };
+        Box::new(handler)
+    }
+
+    fn handle_touch_start(&self) -> EventHandler {
+        let touch_start_x = self.touch_start_x.clone();
+        let handler = move |e: Event| -> JsResult<()> {
+            let touch_event: TouchEvent = e.dyn_into()?;
+            let touch = touch_event.changed_touches().get(0);
+            touch_start_x.set(touch.map(|t| t.client_x()));
+            Ok(())
+        };
+        Box::new(handler)
+    }
+
+    fn handle_touch_end(&self) -> EventHandler {
+        let document_ref = self.document.clone();
+        let touch_start_x = self.touch_start_x.clone();
+        let handler = move |e: Event| -> JsResult<()> {
+            let touch_event: TouchEvent = e.dyn_into()?;
+            let start_x = match touch_start_x.replace(None) {
+                Some(x) => x,
+                None => return Ok(()),
             };
-            render_content(&content)?;
+            let touch = touch_event.changed_touches().get(0).ok_or("no touch")?;
+            let distance = touch.client_x() - start_x;
+            if distance.abs() < SWIPE_MIN_DISTANCE {
+                return Ok(());
+            }
+            // swiping to the left brings in what's on the right, like the right arrow does
+            let side = if distance < 0 { Cmp::More } else { Cmp::Less };
+            turn_page(&document_ref, side)
+        };
+        Box::new(handler)
+    }
+
+    /// Scrolls vertical text sideways with the mouse wheel, towards the end of the line flow.
+    fn handle_wheel(&self) -> EventHandler {
+        let document_ref = self.document.clone();
+        let handler = move |e: Event| -> JsResult<()> {
+            let writing_mode = match document_ref.borrow().as_ref() {
+                Some(doc) => doc.writing_mode(),
+                None => return Ok(()),
+            };
+            let wheel_event: WheelEvent = e.dyn_into()?;
+            let delta = wheel_event.delta_y();
+            let delta_x = match writing_mode {
+                WritingMode::VerticalRl => -delta,
+                WritingMode::VerticalLr => delta,
+                WritingMode::HorizontalTb => return Ok(()),
+            };
+            wheel_event.prevent_default();
+            let content_div = document()?
+                .get_element_by_id("content")
+                .ok_or("no #content")?;
+            content_div.scroll_by_with_x_and_y(delta_x, 0.0);
             Ok(())
         };
         Box::new(handler)
     }
 
+    fn handle_progress_change(&self) -> EventHandler {
+        let document_ref = self.document.clone();
+        let handler = move |_| -> JsResult<()> {
+            match document_ref.borrow_mut().as_mut() {
+                Some(doc) => render_progress(doc.as_mut()),
+                None => Ok(()),
+            }
+        };
+        Box::new(handler)
+    }
+
+    fn handle_go_to_page(&self) -> EventHandler {
+        let document_ref = self.document.clone();
+        let handler = move |e: Event| -> JsResult<()> {
+            let input: HtmlInputElement = e.target().ok_or("no event target")?.dyn_into()?;
+            let label = input.value();
+            let mut document_option = document_ref.borrow_mut();
+            let doc = document_option
+                .as_mut()
+                .ok_or("no book loaded yet")?
+                .as_mut();
+            let epub = doc.epub_features().ok_or("no page list in this book")?;
+            let page = epub
+                .page_list()?
+                .iter()
+                .find(|p| p.label.eq_ignore_ascii_case(label.trim()))
+                .cloned();
+            // put the current page back in the input when there's no such page
+            let content = match page {
+                Some(ref page) => epub.chapter_by_page(page)?,
+                None => return render_page(doc),
+            };
+            render_chapter(doc, &content)?;
+            update_history_state(doc, true)?;
+            scroll_to_fragment(page.and_then(|p| p.fragment))
+        };
+        Box::new(handler)
+    }
+
+    fn handle_fit(&self) -> EventHandler {
+        let handler = move |_| -> JsResult<()> { render_fit(true) };
+        Box::new(handler)
+    }
+
     fn handle_font(&self, cmp: Cmp) -> EventHandler {
         let handler = move |_| -> JsResult<()> {
             let elem: HtmlElement = document()?
@@ -172,6 +371,72 @@ impl LeedorApp {
         Box::new(handler)
     }
 
+    /// Reopens the book at the start of the rendition picked in #rendition.
+    fn handle_rendition(&self) -> EventHandler {
+        let document_ref = self.document.clone();
+        let handler = move |e: Event| -> JsResult<()> {
+            let select: HtmlSelectElement = e.target().ok_or("no event target")?.dyn_into()?;
+            let rendition_idx = select.selected_index();
+            if rendition_idx < 0 {
+                return Ok(());
+            }
+            let mut document_option = document_ref.borrow_mut();
+            let doc = document_option
+                .as_mut()
+                .ok_or("no book loaded yet")?
+                .as_mut();
+            doc.epub_features()
+                .ok_or("no renditions in this book")?
+                .select_rendition(rendition_idx as usize)?;
+            let start = doc.start_location();
+            let fragment = start.fragment.clone();
+            let first_chapter = doc.go_to_location(start)?;
+            render_toc(&doc.toc()?)?;
+            render_direction(doc.direction())?;
+            render_writing_mode(doc.writing_mode())?;
+            render_export(Some(doc.chapter_count()))?;
+            render_chapter(doc, &first_chapter)?;
+            // the rendition starts a history of its own
+            update_history_state(doc, false)?;
+            scroll_to_fragment(fragment.as_ref())
+        };
+        Box::new(handler)
+    }
+
+    /// Downloads the book, or the chapters picked in #export-range, as a single HTML file, which
+    /// can be read without a reader.
+    fn handle_export(&self) -> EventHandler {
+        let document_ref = self.document.clone();
+        let handler = move |_| -> JsResult<()> {
+            let mut document_option = document_ref.borrow_mut();
+            let doc = document_option
+                .as_mut()
+                .ok_or("no book loaded yet")?
+                .as_mut();
+            let title = doc.metadata().title.unwrap_or_else(|| "book".to_string());
+            let range = export_range(doc)?;
+            let html = export::export_document(doc, range)?;
+            download(&format!("{}.html", title), &html, "text/html")
+        };
+        Box::new(handler)
+    }
+
+    /// Shows the first and last chapter inputs when exporting a range of chapters.
+    fn handle_export_range(&self) -> EventHandler {
+        let handler = move |e: Event| -> JsResult<()> {
+            let select: HtmlSelectElement = e.target().ok_or("no event target")?.dyn_into()?;
+            let bounds = document()?
+                .get_element_by_id("export-bounds")
+                .ok_or("no #export-bounds")?;
+            if select.value() == "chapters" {
+                bounds.class_list().remove_1("hidden")
+            } else {
+                bounds.class_list().add_1("hidden")
+            }
+        };
+        Box::new(handler)
+    }
+
     fn handle_file_change(&self) -> EventHandler {
         let onload_rc = Rc::new(Closure::wrap(self.handle_file_load()));
         let handler = move |e: Event| -> JsResult<()> {
@@ -189,36 +454,22 @@ impl LeedorApp {
 
     // TODO: fix memory leaks when loading new epubs.
     fn handle_file_load(&self) -> EventHandler {
-        let epub_ref = self.epub.clone();
+        let document_ref = self.document.clone();
         let handler = move |e: Event| -> JsResult<()> {
             let file_reader: FileReader = e.target().ok_or("no event target")?.dyn_into()?;
             let array_buffer: ArrayBuffer = file_reader.result()?.into();
-            load_from_buffer(&epub_ref, &array_buffer)
+            load_from_buffer(&document_ref, &array_buffer, None)
         };
         Box::new(handler)
     }
 
     fn handle_sample_click(&self) -> impl OnceEventHandler {
-        let epub_ref = self.epub.clone();
+        let document_ref = self.document.clone();
         |e: Event| -> JsResult<()> {
             e.prevent_default();
             let clicked_elem: Element = e.target().ok_or("no event target")?.dyn_into()?;
             let href = clicked_elem.get_attribute("href").ok_or("no href")?;
-            let window = web_sys::window().ok_or("no window")?;
-            let fetch_promise = window.fetch_with_str(&format!("static/{}.epub", &href[1..]));
-            let future = JsFuture::from(fetch_promise)
-                .and_then(|response_val: JsValue| -> JsResult<Promise> {
-                    let response: Response = response_val.into();
-                    response.array_buffer()
-                })
-                .and_then(JsFuture::from)
-                .and_then(move |array_buffer_val: JsValue| -> JsResult<JsValue> {
-                    let array_buffer: ArrayBuffer = array_buffer_val.into();
-                    load_from_buffer(&epub_ref, &array_buffer)?;
-                    Ok(JsValue::from(0))
-                });
-            future_to_promise(future);
-            Ok(())
+            load_sample(document_ref, &href[1..], None)
         }
     }
 }
@@ -232,6 +483,56 @@ fn document() -> JsResult<Document> {
     Err(JsValue::from("no document"))
 }
 
+/// Has the browser save `contents` as a file named `file_name`.
+fn download(file_name: &str, contents: &str, media_type: &str) -> JsResult<()> {
+    let parts = Array::new();
+    parts.push(&JsValue::from_str(contents));
+    let blob =
+        Blob::new_with_str_sequence_and_options(&parts, BlobPropertyBag::new().type_(media_type))?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+    let anchor: HtmlAnchorElement = document()?.create_element("a")?.dyn_into()?;
+    anchor.set_attribute("href", &url)?;
+    anchor.set_download(file_name);
+    anchor.click();
+    // revoking the URL right away can cancel the download, so wait until the click is handled
+    let revoke = Closure::once_into_js(move || web_sys::Url::revoke_object_url(&url));
+    let window = web_sys::window().ok_or("no window")?;
+    window.set_timeout_with_callback(revoke.unchecked_ref())?;
+    Ok(())
+}
+
+/// The spine items picked in #export-range: the current one, the ones from #export-from to
+/// #export-to, counting from 1, or all of them when that's `None`.
+fn export_range(doc: &dyn document::Document) -> JsResult<Option<Range<usize>>> {
+    let document = document()?;
+    let select: HtmlSelectElement = document
+        .get_element_by_id("export-range")
+        .ok_or("no #export-range")?
+        .dyn_into()?;
+    let bound = |id: &str| -> JsResult<usize> {
+        let input: HtmlInputElement = document
+            .get_element_by_id(id)
+            .ok_or("no chapter input")?
+            .dyn_into()?;
+        input.value().parse().map_err(|_| "invalid chapter".into())
+    };
+    let range = match select.value().as_str() {
+        "chapter" => {
+            let current_idx = doc.current_idx()?;
+            Some(current_idx..current_idx + 1)
+        }
+        "chapters" => {
+            let (first, last) = (bound("export-from")?, bound("export-to")?);
+            if first == 0 || last < first {
+                return Err("invalid chapter range".into());
+            }
+            Some(first - 1..last)
+        }
+        _ => None,
+    };
+    Ok(range)
+}
+
 fn add_event_listener<T>(target: T, event: &str, handler: EventHandler) -> JsResult<()>
 where
     T: Into<EventTarget>,
@@ -255,15 +556,269 @@ where
     Ok(())
 }
 
-fn load_from_buffer(epub_ref: &EpubRef, array_buffer: &ArrayBuffer) -> JsResult<()> {
+/// Reopens the sample book and location a shared URL points at, e.g.
+/// `#quijote/cfi/epubcfi(/6/26!/4/2)`.
+fn open_from_hash(document_ref: DocumentRef) -> JsResult<()> {
+    let window = web_sys::window().ok_or("no window")?;
+    let (book, location) = split_hash(&window.location().hash()?);
+    if book.is_empty() || !book.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Ok(());
+    }
+    let samples = document()?
+        .get_element_by_id("samples")
+        .ok_or("no #samples")?;
+    if samples
+        .query_selector(&format!("a[href='#{}']", book))?
+        .is_none()
+    {
+        return Ok(());
+    }
+    load_sample(document_ref, &book, location)
+}
+
+/// Splits a URL hash into the sample book it names and the location in it, percent-decoded.
+fn split_hash(hash: &str) -> (String, Option<String>) {
+    let hash = percent_decode(hash.trim_start_matches('#').as_bytes()).decode_utf8_lossy();
+    let mut parts = hash.splitn(2, '/');
+    let book = parts.next().unwrap_or_default().to_string();
+    (book, parts.next().map(String::from))
+}
+
+/// Fetches one of the sample books and opens it at `location`, a location from the URL hash.
+fn load_sample(document_ref: DocumentRef, book: &str, location: Option<String>) -> JsResult<()> {
+    let window = web_sys::window().ok_or("no window")?;
+    let fetch_promise = window.fetch_with_str(&format!("static/{}.epub", book));
+    let book = book.to_string();
+    let future = JsFuture::from(fetch_promise)
+        .and_then(|response_val: JsValue| -> JsResult<Promise> {
+            let response: Response = response_val.into();
+            response.array_buffer()
+        })
+        .and_then(JsFuture::from)
+        .and_then(move |array_buffer_val: JsValue| -> JsResult<JsValue> {
+            let array_buffer: ArrayBuffer = array_buffer_val.into();
+            load_from_buffer(&document_ref, &array_buffer, Some(&book))?;
+            if let Some(location) = location {
+                go_to_hash_location(&document_ref, &location)?;
+            }
+            Ok(JsValue::from(0))
+        });
+    future_to_promise(future);
+    Ok(())
+}
+
+/// Moves to a location from the URL hash: `chapter/<spine index>[/<fragment>]` or `cfi/<CFI>`.
+fn go_to_hash_location(document_ref: &DocumentRef, hash_location: &str) -> JsResult<()> {
+    let mut document_option = document_ref.borrow_mut();
+    let doc = document_option
+        .as_mut()
+        .ok_or("no book loaded yet")?
+        .as_mut();
+    let mut parts = hash_location.splitn(2, '/');
+    let location = match (parts.next(), parts.next()) {
+        (Some("cfi"), Some(cfi)) => doc
+            .epub_features()
+            .ok_or("no CFIs for this book")?
+            .resolve_cfi(cfi)?,
+        (Some("chapter"), Some(rest)) => {
+            let mut parts = rest.splitn(2, '/');
+            let spine_idx = parts
+                .next()
+                .unwrap_or_default()
+                .parse()
+                .map_err(|_| "invalid chapter in URL")?;
+            Location::new(spine_idx, parts.next())
+        }
+        _ => return Ok(()),
+    };
+    let fragment = location.fragment.clone();
+    let content = doc.go_to_location(location)?;
+    render_chapter(doc, &content)?;
+    update_history_state(doc, false)?;
+    scroll_to_fragment(fragment.as_ref())
+}
+
+/// Navigates like the left (`Cmp::Less`) or right (`Cmp::More`) arrow does, which is backwards
+/// or forwards depending on the book's page progression direction.
+fn turn_page(document_ref: &DocumentRef, side: Cmp) -> JsResult<()> {
+    let mut document_option = document_ref.borrow_mut();
+    let doc = document_option
+        .as_mut()
+        .ok_or("no book loaded yet")?
+        .as_mut();
+    // two-page spreads are turned as a whole
+    let current_idx = doc.current_idx()?;
+    let (first, last) = shown_spread(doc, current_idx)?.unwrap_or((current_idx, current_idx));
+    let content = if is_forward(side, doc.direction()) {
+        doc.chapter_after(last)?
+    } else {
+        doc.chapter_before(first)?
+    };
+    render_chapter(doc, &content)?;
+    update_history_state(doc, false)
+}
+
+fn is_forward(side: Cmp, direction: Direction) -> bool {
+    match side {
+        Cmp::More => direction == Direction::Ltr,
+        Cmp::Less => direction == Direction::Rtl,
+    }
+}
+
+/// Opens the book in `array_buffer`, logging any problems found in EPUBs to the console and
+/// warning the reader about broken ones. `book` names the sample it is, if any.
+fn load_from_buffer(
+    document_ref: &DocumentRef,
+    array_buffer: &ArrayBuffer,
+    book: Option<&str>,
+) -> JsResult<()> {
     let mut bytes = vec![0; array_buffer.byte_length() as usize];
     Uint8Array::new(&array_buffer).copy_to(&mut bytes);
-    let mut epub_option = epub_ref.borrow_mut();
-    *epub_option = Some(Epub::new(bytes)?);
-    let epub = epub_option.as_mut().ok_or("no epub")?;
-    let first_chapter = epub.chapter(0)?;
-    render_toc(&epub.toc()?)?;
-    render_content(&first_chapter)
+    let format = format::detect(&bytes);
+    if format.is_epub() {
+        for diagnostic in validate(&bytes) {
+            console::warn_1(&JsValue::from_str(&diagnostic.to_string()));
+        }
+    }
+    let mut new_doc = match document::open(bytes) {
+        Ok(doc) => doc,
+        Err(err @ Error::DrmError(_))
+        | Err(err @ Error::FormatError(_))
+        | Err(err @ Error::ConversionError(..)) => return render_error(&err.to_string()),
+        Err(err) => return Err(err.into()),
+    };
+    render_error("")?;
+    if format == Format::BrokenEpub {
+        render_warning("This book is damaged, so parts of it may not show up right.")?;
+    } else {
+        render_warning("")?;
+    }
+    render_fit(new_doc.is_paged_images())?;
+    match new_doc.epub_features() {
+        Some(epub) => render_renditions(epub.renditions())?,
+        None => render_renditions(&[])?,
+    }
+    let exportable = new_doc.epub_features().is_some();
+    let mut document_option = document_ref.borrow_mut();
+    *document_option = Some(new_doc);
+    let doc = document_option.as_mut().ok_or("no book")?.as_mut();
+    let start = doc.start_location();
+    let fragment = start.fragment.clone();
+    let first_chapter = doc.go_to_location(start)?;
+    render_toc(&doc.toc()?)?;
+    render_direction(doc.direction())?;
+    render_writing_mode(doc.writing_mode())?;
+    render_export(Some(doc.chapter_count()).filter(|_| exportable))?;
+    render_chapter(doc, &first_chapter)?;
+    set_history_state(doc, book, false)?;
+    scroll_to_fragment(fragment.as_ref())
+}
+
+/// Records the reader's location in the browser history, keeping the book in the URL hash.
+fn update_history_state(doc: &mut dyn document::Document, push: bool) -> JsResult<()> {
+    let window = web_sys::window().ok_or("no window")?;
+    let (book, _) = split_hash(&window.location().hash()?);
+    set_history_state(doc, Some(book.as_str()).filter(|b| !b.is_empty()), push)
+}
+
+/// Records the reader's location in the browser history. Sample books also get a URL that leads
+/// back to the location, e.g. `#quijote/cfi/epubcfi(/6/26!/4/2)`, or `#quijote/chapter/12` for
+/// locations a CFI can't be built for, while other books get no hash at all since there would be
+/// no way to reopen them. Books without a navigation history get no history state either.
+fn set_history_state(
+    doc: &mut dyn document::Document,
+    book: Option<&str>,
+    push: bool,
+) -> JsResult<()> {
+    let location = doc.location().cloned();
+    let url = match (book, location) {
+        (Some(book), Some(location)) => match (cfi(doc, &location), &location.fragment) {
+            (Some(cfi), _) => format!("#{}/cfi/{}", book, encode_hash(&cfi)),
+            (None, Some(fragment)) => format!(
+                "#{}/chapter/{}/{}",
+                book,
+                location.spine_idx,
+                encode_hash(fragment)
+            ),
+            (None, None) => format!("#{}/chapter/{}", book, location.spine_idx),
+        },
+        _ => String::new(),
+    };
+    let window = web_sys::window().ok_or("no window")?;
+    let history = window.history()?;
+    let state = JsValue::from(doc.history_position() as u32);
+    if push {
+        history.push_state_with_url(&state, "", Some(&url))
+    } else {
+        history.replace_state_with_url(&state, "", Some(&url))
+    }
+}
+
+/// A CFI for `location`, for books that have them.
+fn cfi(doc: &mut dyn document::Document, location: &Location) -> Option<String> {
+    doc.epub_features()?.cfi(location).ok()
+}
+
+fn encode_hash(location: &str) -> String {
+    utf8_percent_encode(location, HASH_ENCODE_SET).collect()
+}
+
+fn scroll_to_fragment<S: AsRef<str>>(fragment: Option<S>) -> JsResult<()> {
+    let fragment = match fragment {
+        Some(ref s) => s.as_ref(),
+        None => return Ok(()),
+    };
+    let shadow_root = document()?
+        .get_element_by_id("content")
+        .ok_or("no #content")?
+        .shadow_root()
+        .ok_or("no shadow root")?;
+    if let Some(elem) = shadow_root.get_element_by_id(fragment) {
+        elem.scroll_into_view();
+    }
+    Ok(())
+}
+
+/// Lays the content out in the book's writing mode. Vertical text scrolls sideways, starting on
+/// the right for `vertical-rl`.
+fn render_writing_mode(writing_mode: WritingMode) -> JsResult<()> {
+    let content_div = document()?
+        .get_element_by_id("content")
+        .ok_or("no #content")?;
+    let class_list = content_div.class_list();
+    class_list.remove_2("vertical-rl", "vertical-lr")?;
+    match writing_mode {
+        WritingMode::VerticalRl => class_list.add_1("vertical-rl"),
+        WritingMode::VerticalLr => class_list.add_1("vertical-lr"),
+        WritingMode::HorizontalTb => Ok(()),
+    }
+}
+
+/// Sets the content's base direction, so columns flow like the book's pages, and swaps the
+/// arrows' titles to match what they do.
+fn render_direction(direction: Direction) -> JsResult<()> {
+    let document = document()?;
+    let content_div = document.get_element_by_id("content").ok_or("no #content")?;
+    let prev_button = document.get_element_by_id("prev").ok_or("no #prev")?;
+    let next_button = document.get_element_by_id("next").ok_or("no #next")?;
+    let (dir, left_title, right_title) = match direction {
+        Direction::Ltr => ("ltr", "Previous", "Next"),
+        Direction::Rtl => ("rtl", "Next", "Previous"),
+    };
+    content_div.set_attribute("dir", dir)?;
+    prev_button.set_attribute("title", left_title)?;
+    next_button.set_attribute("title", right_title)?;
+    Ok(())
+}
+
+/// Shows a footnote or endnote over the chapter, which stays where it was. Like chapters, the
+/// note gets a shadow root of its own so that the book's styles stay out of the page.
+fn render_note(note: &str) -> JsResult<()> {
+    let note_div = document()?.get_element_by_id("note").ok_or("no #note")?;
+    let note_root = note_div.shadow_root().ok_or("no shadow root")?;
+    note_root.set_inner_html(note);
+    note_div.class_list().remove_1("hidden")?;
+    Ok(())
 }
 
 fn render_toc(toc: &[TocItem]) -> JsResult<()> {
@@ -281,11 +836,377 @@ fn render_toc(toc: &[TocItem]) -> JsResult<()> {
     Ok(())
 }
 
-fn render_content(content: &str) -> JsResult<()> {
+/// Offers the renditions of a book that has more than one in #rendition, e.g. a reflowable and
+/// a fixed-layout one, or hides it. The first rendition is the one that's open.
+fn render_renditions(renditions: &[Rendition]) -> JsResult<()> {
+    let document = document()?;
+    let rendition_nav = document
+        .get_element_by_id("rendition-nav")
+        .ok_or("no #rendition-nav")?;
+    let select = document
+        .get_element_by_id("rendition")
+        .ok_or("no #rendition")?;
+    select.set_inner_html("");
+    if renditions.len() < 2 {
+        return rendition_nav.class_list().add_1("hidden");
+    }
+    for (idx, rendition) in renditions.iter().enumerate() {
+        let label = rendition.label.clone().unwrap_or_else(|| {
+            let properties: Vec<String> = [
+                &rendition.layout,
+                &rendition.language,
+                &rendition.access_mode,
+                &rendition.media,
+            ]
+            .iter()
+            .filter_map(|p| (*p).clone())
+            .collect();
+            if properties.is_empty() {
+                (idx + 1).to_string()
+            } else {
+                properties.join(", ")
+            }
+        });
+        let option = document.create_element("option")?;
+        option.set_text_content(Some(&label));
+        select.append_child(&option)?;
+    }
+    rendition_nav.class_list().remove_1("hidden")
+}
+
+/// Renders the chapter the book is currently at, which is `content`. Fixed-layout pages are
+/// scaled to fit and shown in pairs when the book asks for two-page spreads.
+fn render_chapter(doc: &mut dyn document::Document, content: &str) -> JsResult<()> {
+    if doc.is_paged_images() {
+        // pages fit to the width scroll down like reflowable chapters do
+        render_content(
+            &format!("{}{}", COMIC_PAGE_STYLE, content),
+            Layout::Reflowable,
+        )?;
+        return render_progress(doc);
+    }
+    let current_idx = doc.current_idx()?;
+    let is_fixed = match doc.epub_features() {
+        Some(epub) => epub.layout(current_idx)?.layout != Layout::Reflowable,
+        None => false,
+    };
+    if !is_fixed {
+        render_content(content, Layout::Reflowable)?;
+        return render_progress(doc);
+    }
+    let spread = match shown_spread(doc, current_idx)? {
+        Some((first, second)) => vec![first, second],
+        None => vec![current_idx],
+    };
+    let direction = doc.direction();
+    let mut pages = vec![];
+    {
+        let epub = doc.epub_features().ok_or("no fixed layout in this book")?;
+        for idx in spread {
+            let page = if idx == current_idx {
+                content.to_string()
+            } else {
+                epub.spine_markup(idx)?
+            };
+            pages.push((page, epub.viewport(idx)?));
+        }
+    }
+    // pages are laid out left to right
+    if direction == Direction::Rtl {
+        pages.reverse();
+    }
+    render_fixed_layout(&pages)?;
+    render_progress(doc)
+}
+
+/// Shows how far into the book the reader is, along with how long the rest of it would take at
+/// the reading speed in #reading-speed. Books of page images show the page the reader is on
+/// instead, along with the book's title.
+fn render_progress(doc: &mut dyn document::Document) -> JsResult<()> {
+    if doc.is_paged_images() {
+        return render_page_number(doc);
+    }
+    let document = document()?;
+    let content_div = document.get_element_by_id("content").ok_or("no #content")?;
+    let within_item = scroll_fraction(&content_div, doc.writing_mode());
+    let progress = doc.progress(doc.current_idx()?, within_item)?;
+    let reading_speed: HtmlInputElement = document
+        .get_element_by_id("reading-speed")
+        .ok_or("no #reading-speed")?
+        .dyn_into()?;
+    let words_per_minute = reading_speed
+        .value()
+        .parse()
+        .unwrap_or(WORDS_PER_MINUTE_DEFAULT);
+    let progress_bar = document
+        .get_element_by_id("progress-bar")
+        .ok_or("no #progress-bar")?;
+    progress_bar.set_attribute("value", &progress.fraction.to_string())?;
+    let progress_text = document
+        .get_element_by_id("progress-text")
+        .ok_or("no #progress-text")?;
+    progress_text.set_text_content(Some(&format!(
+        "Chapter {} of {} · {}% · {} left",
+        progress.item_idx + 1,
+        progress.item_count,
+        (progress.fraction * 100.0).floor(),
+        format_minutes(progress.minutes_left(words_per_minute)),
+    )));
+    let progress_div = document
+        .get_element_by_id("progress")
+        .ok_or("no #progress")?;
+    progress_div.class_list().remove_1("hidden")?;
+    let speed_nav = document
+        .get_element_by_id("speed-nav")
+        .ok_or("no #speed-nav")?;
+    speed_nav.class_list().remove_1("hidden")?;
+    render_page(doc)
+}
+
+/// Shows the page of a book of page images the reader is on, along with the book's title.
+fn render_page_number(doc: &dyn document::Document) -> JsResult<()> {
+    let document = document()?;
+    let page_number = doc.current_idx()? + 1;
+    let page_count = doc.chapter_count();
+    let progress_bar = document
+        .get_element_by_id("progress-bar")
+        .ok_or("no #progress-bar")?;
+    let fraction = page_number as f64 / page_count as f64;
+    progress_bar.set_attribute("value", &fraction.to_string())?;
+    let progress_text = document
+        .get_element_by_id("progress-text")
+        .ok_or("no #progress-text")?;
+    let mut text = format!("Page {} of {}", page_number, page_count);
+    if let Some(title) = doc.metadata().title {
+        text = format!("{} \u{2014} {}", title, text);
+    }
+    progress_text.set_text_content(Some(&text));
+    for id in &["page-nav", "speed-nav"] {
+        let elem = document.get_element_by_id(id).ok_or("no progress label")?;
+        elem.class_list().add_1("hidden")?;
+    }
+    let progress_div = document
+        .get_element_by_id("progress")
+        .ok_or("no #progress")?;
+    progress_div.class_list().remove_1("hidden")
+}
+
+/// Shows #fit and sizes comic pages the way it says, or hides it when `is_comic` is false.
+fn render_fit(is_comic: bool) -> JsResult<()> {
+    let document = document()?;
+    let fit_nav = document.get_element_by_id("fit-nav").ok_or("no #fit-nav")?;
+    let content_div = document.get_element_by_id("content").ok_or("no #content")?;
+    content_div.class_list().remove_2("fit-width", "fit-page")?;
+    if !is_comic {
+        return fit_nav.class_list().add_1("hidden");
+    }
+    let fit: HtmlSelectElement = document
+        .get_element_by_id("fit")
+        .ok_or("no #fit")?
+        .dyn_into()?;
+    content_div.class_list().add_1(&fit.value())?;
+    fit_nav.class_list().remove_1("hidden")
+}
+
+/// Shows the download button and limits the chapters it exports to the book's, preselecting all
+/// of them, or hides it for books that can't be exported, which have no `chapter_count`.
+fn render_export(chapter_count: Option<usize>) -> JsResult<()> {
+    let document = document()?;
+    let chapter_count = match chapter_count {
+        Some(chapter_count) => chapter_count,
+        None => {
+            for id in &["export", "export-nav"] {
+                let elem = document.get_element_by_id(id).ok_or("no export control")?;
+                elem.class_list().add_1("hidden")?;
+            }
+            return Ok(());
+        }
+    };
+    for (id, value) in &[("export-from", 1), ("export-to", chapter_count)] {
+        let input: HtmlInputElement = document
+            .get_element_by_id(id)
+            .ok_or("no chapter input")?
+            .dyn_into()?;
+        input.set_max(&chapter_count.to_string());
+        input.set_value(&value.to_string());
+    }
+    for id in &["export", "export-nav"] {
+        let elem = document.get_element_by_id(id).ok_or("no export control")?;
+        elem.class_list().remove_1("hidden")?;
+    }
+    Ok(())
+}
+
+/// Shows the print page the reader is on, for books with a page list. A page counts as reached
+/// once its start marker makes it to the middle of the view. Books whose page list can't be read
+/// are shown as having none.
+fn render_page(doc: &mut dyn document::Document) -> JsResult<()> {
+    let document = document()?;
+    let page_nav = document
+        .get_element_by_id("page-nav")
+        .ok_or("no #page-nav")?;
+    let writing_mode = doc.writing_mode();
+    let epub = match doc.epub_features() {
+        Some(epub) => epub,
+        None => return page_nav.class_list().add_1("hidden"),
+    };
+    let has_pages = match epub.page_list() {
+        Ok(pages) => !pages.is_empty(),
+        Err(err) => {
+            console::warn_1(&JsValue::from_str(&err.to_string()));
+            false
+        }
+    };
+    if !has_pages {
+        return page_nav.class_list().add_1("hidden");
+    }
+    let content_div = document.get_element_by_id("content").ok_or("no #content")?;
+    let shadow_root = content_div.shadow_root().ok_or("no shadow root")?;
+    let view = content_div.get_bounding_client_rect();
+    let page = epub.current_page(&|id| {
+        let marker = match shadow_root.get_element_by_id(id) {
+            Some(elem) => elem.get_bounding_client_rect(),
+            None => return true,
+        };
+        match writing_mode {
+            WritingMode::HorizontalTb => marker.top() < view.top() + view.height() / 2.0,
+            WritingMode::VerticalRl => marker.right() > view.left() + view.width() / 2.0,
+            WritingMode::VerticalLr => marker.left() < view.left() + view.width() / 2.0,
+        }
+    })?;
+    let page_input: HtmlInputElement = document
+        .get_element_by_id("page")
+        .ok_or("no #page")?
+        .dyn_into()?;
+    page_input.set_value(&page.unwrap_or_default());
+    page_nav.class_list().remove_1("hidden")
+}
+
+/// How far the content is scrolled along the direction the text flows in, from 0 to 1.
+fn scroll_fraction(content_div: &Element, writing_mode: WritingMode) -> f64 {
+    let (position, range) = if writing_mode.is_vertical() {
+        (
+            content_div.scroll_left().abs(),
+            content_div.scroll_width() - content_div.client_width(),
+        )
+    } else {
+        (
+            content_div.scroll_top(),
+            content_div.scroll_height() - content_div.client_height(),
+        )
+    };
+    if range <= 0 {
+        return 0.0;
+    }
+    f64::from(position) / f64::from(range)
+}
+
+fn format_minutes(minutes: u32) -> String {
+    match (minutes / 60, minutes % 60) {
+        (0, m) => format!("{} min", m),
+        (h, 0) => format!("{} h", h),
+        (h, m) => format!("{} h {} min", h, m),
+    }
+}
+
+/// The two-page spread chapter `item_idx` is shown in, if any. Only EPUBs have spreads.
+fn shown_spread(
+    doc: &mut dyn document::Document,
+    item_idx: usize,
+) -> JsResult<Option<(usize, usize)>> {
+    let epub = match doc.epub_features() {
+        Some(epub) => epub,
+        None => return Ok(None),
+    };
+    let window = web_sys::window().ok_or("no window")?;
+    let width = window.inner_width()?.as_f64().unwrap_or_default();
+    let height = window.inner_height()?.as_f64().unwrap_or_default();
+    if !epub.layout(item_idx)?.shows_spread(width > height) {
+        return Ok(None);
+    }
+    Ok(epub.spread(item_idx)?)
+}
+
+/// Scales fixed-layout pages to fit the content area, side by side. Each page is rendered in its
+/// own shadow root so that the styles of one don't apply to the other.
+fn render_fixed_layout(pages: &[(String, Option<(u32, u32)>)]) -> JsResult<()> {
+    let content_div = document()?
+        .get_element_by_id("content")
+        .ok_or("no #content")?;
+    content_div.class_list().add_1("fixed-layout")?;
+    let available_width = f64::from(content_div.client_width());
+    let available_height = f64::from(content_div.client_height());
+    let default_viewport = (
+        available_width as u32 / pages.len() as u32,
+        available_height as u32,
+    );
+    let mut spread_width = 0;
+    let mut spread_height = 0;
+    let mut pages_html = String::new();
+    for (idx, (_, viewport)) in pages.iter().enumerate() {
+        let (width, height) = viewport.unwrap_or(default_viewport);
+        spread_width += width;
+        spread_height = max(spread_height, height);
+        pages_html.push_str(&format!(
+            "<div id=\"page-{}\" style=\"flex: none; width: {}px; height: {}px; \
+             overflow: hidden;\"></div>",
+            idx, width, height
+        ));
+    }
+    let scale = (available_width / f64::from(spread_width.max(1)))
+        .min(available_height / f64::from(spread_height.max(1)));
+    let left = (available_width - f64::from(spread_width) * scale) / 2.0;
+    let spread_html = format!(
+        "<div style=\"display: flex; position: relative; left: {}px; width: {}px; \
+         transform: scale({}); transform-origin: 0 0;\">{}</div>",
+        left, spread_width, scale, pages_html
+    );
+    render_content(&spread_html, Layout::PrePaginated)?;
+    let shadow_root = content_div.shadow_root().ok_or("no shadow root")?;
+    for (idx, (page, _)) in pages.iter().enumerate() {
+        let page_div = shadow_root
+            .get_element_by_id(&format!("page-{}", idx))
+            .ok_or("no page container")?;
+        let page_root = page_div.attach_shadow(&ShadowRootInit::new(ShadowRootMode::Open))?;
+        page_root.set_inner_html(page);
+    }
+    Ok(())
+}
+
+/// Shows a message about a book that couldn't be opened on the welcome screen, or hides it
+/// when `message` is empty.
+fn render_error(message: &str) -> JsResult<()> {
+    let document = document()?;
+    let error = document.get_element_by_id("error").ok_or("no #error")?;
+    error.set_text_content(Some(message));
+    if message.is_empty() {
+        return error.class_list().add_1("hidden");
+    }
+    error.class_list().remove_1("hidden")?;
+    let welcome = document.get_element_by_id("welcome").ok_or("no #welcome")?;
+    welcome.class_list().remove_1("hidden")
+}
+
+fn render_warning(message: &str) -> JsResult<()> {
+    let warning = document()?
+        .get_element_by_id("warning")
+        .ok_or("no #warning")?;
+    warning.set_text_content(Some(message));
+    if message.is_empty() {
+        warning.class_list().add_1("hidden")
+    } else {
+        warning.class_list().remove_1("hidden")
+    }
+}
+
+fn render_content(content: &str, layout: Layout) -> JsResult<()> {
     let document = document()?;
     let welcome = document.get_element_by_id("welcome").ok_or("no #welcome")?;
     welcome.class_list().add_1("hidden")?;
     let content_div = document.get_element_by_id("content").ok_or("no #content")?;
+    if layout == Layout::Reflowable {
+        content_div.class_list().remove_1("fixed-layout")?;
+    }
     let shadow_root = content_div.shadow_root().ok_or("no shadow root")?;
     shadow_root.set_inner_html(content);
     content_div.scroll_with_x_and_y(0.0, 0.0);
@@ -1,7 +1,13 @@
+use crate::encoding;
+use crate::entities;
 use crate::error::Result;
 use minidom::{Children, Element};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::io::{BufReader, Read};
 
+const UNPARSED_SECTIONS: &[(&str, &str)] = &[("<!--", "-->"), ("<![CDATA[", "]]>")];
+
 pub trait Descend {
     fn descendants(&self) -> Descendants;
 }
@@ -33,8 +39,177 @@ impl<'a> Iterator for Descendants<'a> {
     }
 }
 
+/// Concatenates the text in `elem` and all its descendants, in document order.
+pub fn text_content(elem: &Element) -> String {
+    let mut text = String::new();
+    for node in elem.nodes() {
+        if let Some(t) = node.as_text() {
+            text.push_str(t);
+        } else if let Some(child) = node.as_element() {
+            text.push_str(&text_content(child));
+        }
+    }
+    text
+}
+
+/// Serializes `elem` the way chapters are shown.
+pub fn to_markup(elem: &Element) -> Result<String> {
+    let mut bytes = vec![];
+    elem.write_to(&mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
 pub fn parse_xml<R: Read>(contents: R) -> Result<Element> {
-    let buf_reader = BufReader::new(contents);
-    let mut xml_reader = quick_xml::Reader::from_reader(buf_reader);
+    let mut bytes = vec![];
+    BufReader::new(contents).read_to_end(&mut bytes)?;
+    let text = encoding::decode(&bytes);
+    let expanded = expand_entities(&text);
+    let mut xml_reader = quick_xml::Reader::from_reader(expanded.as_bytes());
     Ok(Element::from_reader(&mut xml_reader)?)
 }
+
+/// Replaces references to entities declared in the XHTML DTDs or in the document's internal DTD
+/// subset, which quick-xml doesn't know about. The DOCTYPE is dropped along the way since it
+/// serves no other purpose once its entities are expanded.
+fn expand_entities(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') && !text.contains("<!DOCTYPE") {
+        return Cow::Borrowed(text);
+    }
+    let mut declared = HashMap::new();
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(idx) = rest.find(&['&', '<'][..]) {
+        expanded.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+        if rest.starts_with("<!DOCTYPE") {
+            let len = doctype_len(rest);
+            declared = internal_entities(&rest[..len]);
+            rest = &rest[len..];
+        } else if rest.starts_with('<') {
+            let len = UNPARSED_SECTIONS
+                .iter()
+                .find(|(start, _)| rest.starts_with(start))
+                .and_then(|(_, end)| rest.find(end).map(|i| i + end.len()))
+                .unwrap_or(1);
+            expanded.push_str(&rest[..len]);
+            rest = &rest[len..];
+        } else {
+            let name = rest.find(';').map(|i| &rest[1..i]).unwrap_or("");
+            let replacement = if name.starts_with('#') || entities::is_predefined(name) {
+                None
+            } else if let Some(value) = declared.get(name) {
+                Some(value.clone())
+            } else {
+                entities::lookup(name).map(|c| format!("&#{};", c as u32))
+            };
+            match replacement {
+                Some(r) => {
+                    expanded.push_str(&r);
+                    rest = &rest[name.len() + 2..];
+                }
+                None => {
+                    expanded.push('&');
+                    rest = &rest[1..];
+                }
+            }
+        }
+    }
+    expanded.push_str(rest);
+    Cow::Owned(expanded)
+}
+
+fn doctype_len(doctype: &str) -> usize {
+    let end = match (doctype.find('['), doctype.find('>')) {
+        (Some(bracket_idx), Some(gt_idx)) if bracket_idx < gt_idx => {
+            doctype.find("]>").map(|i| i + 2)
+        }
+        (_, gt_idx) => gt_idx.map(|i| i + 1),
+    };
+    end.unwrap_or(doctype.len())
+}
+
+/// Collects the general entities declared as `<!ENTITY name "value">` in a DOCTYPE.
+fn internal_entities(doctype: &str) -> HashMap<String, String> {
+    let mut declared = HashMap::new();
+    for decl in doctype.split("<!ENTITY").skip(1) {
+        let decl = decl.trim_start();
+        if decl.starts_with('%') {
+            continue; // parameter entities only matter to the DTD itself
+        }
+        let name_end = decl.find(char::is_whitespace).unwrap_or(decl.len());
+        let value_part = decl[name_end..].trim_start();
+        let quote = match value_part.chars().next() {
+            Some(c) if c == '"' || c == '\'' => c,
+            _ => continue, // external entities aren't supported
+        };
+        if let Some(value_end) = value_part[1..].find(quote) {
+            let value = escape_entity_value(&value_part[1..=value_end]);
+            declared.insert(decl[..name_end].to_string(), value);
+        }
+    }
+    declared
+}
+
+/// Escapes an entity value so that it reads as plain text both in content and in attribute
+/// values, whichever quotes they use. Markup in the value is kept as text rather than parsed, and
+/// only character references and predefined entities are left as references.
+fn escape_entity_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for (idx, c) in value.char_indices() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '&' => {
+                let rest = &value[idx + 1..];
+                let name = rest.find(';').map(|i| &rest[..i]).unwrap_or("");
+                let is_reference = entities::is_predefined(name)
+                    || (name.starts_with('#') && entities::lookup(name).is_some());
+                escaped.push_str(if is_reference { "&" } else { "&amp;" });
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xhtml_named_entities() -> Result<()> {
+        let doc = parse_xml(&b"<p>a&nbsp;b&mdash;caf&eacute; &amp; &lt;tea&gt;</p>"[..])?;
+        assert_eq!("a\u{a0}b\u{2014}caf\u{e9} & <tea>", doc.text());
+        Ok(())
+    }
+
+    #[test]
+    fn internal_subset_entities() -> Result<()> {
+        let contents = br#"<?xml version="1.0"?>
+            <!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.1//EN" "xhtml11.dtd" [
+              <!ENTITY author "Rosa Luxemburg">
+              <!ENTITY % param "ignored">
+            ]>
+            <p title="x">&author;&nbsp;<![CDATA[&author;]]></p>"#;
+        let doc = parse_xml(&contents[..])?;
+        assert_eq!("Rosa Luxemburg\u{a0}&author;", doc.text());
+        Ok(())
+    }
+
+    #[test]
+    fn internal_entities_are_text() -> Result<()> {
+        let contents = br#"<!DOCTYPE p [
+              <!ENTITY quote 'say "hi" &amp; &#233; &unknown;'>
+              <!ENTITY markup "<b title='x'>bold</b>">
+            ]>
+            <p title="&quote;" class='&quote;'>&markup;</p>"#;
+        let doc = parse_xml(&contents[..])?;
+        let quote = "say \"hi\" & \u{e9} &unknown;";
+        assert_eq!(Some(quote), doc.attr("title"));
+        assert_eq!(Some(quote), doc.attr("class"));
+        assert_eq!("<b title='x'>bold</b>", doc.text());
+        Ok(())
+    }
+}